@@ -0,0 +1,138 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{error::FilmanError, journal::Operation};
+
+/// The text the user edits, one name per line
+pub fn names_text(paths: &[PathBuf]) -> Result<String, FilmanError> {
+    let mut text = String::new();
+    for path in paths {
+        let name = path
+            .file_name()
+            .ok_or(FilmanError::PathHasNoFilename)?
+            .to_str()
+            .ok_or(FilmanError::UnicodeError)?;
+        if name.contains('\n') {
+            return Err(FilmanError::CommandError(format!(
+                "Can not bulk rename {:?} because it contains a newline",
+                name
+            )));
+        }
+        text.push_str(name);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Pairs every path with the name on the same line of `edited`. Unchanged
+/// names are left out. Names are relative to the directory of the path.
+pub fn parse_edited(
+    paths: &[PathBuf],
+    edited: &str,
+) -> Result<Vec<(PathBuf, PathBuf)>, FilmanError> {
+    let names = edited.lines().collect::<Vec<_>>();
+    if names.len() != paths.len() {
+        return Err(FilmanError::CommandError(format!(
+            "Expected {} names but got {}",
+            paths.len(),
+            names.len()
+        )));
+    }
+
+    let mut renames = vec![];
+    for (path, name) in paths.iter().zip(names) {
+        if name.trim().is_empty() {
+            return Err(FilmanError::CommandError(format!(
+                "Empty name for {}",
+                path.display()
+            )));
+        }
+        // Entries are only renamed within their own directory
+        if name.contains('/') || name == "." || name == ".." {
+            return Err(FilmanError::CommandError(format!(
+                "Invalid new name {:?} for {}",
+                name,
+                path.display()
+            )));
+        }
+        let parent = path.parent().ok_or(FilmanError::NoParentError)?;
+        let target = parent.join(name);
+        if target != *path {
+            renames.push((path.clone(), target));
+        }
+    }
+
+    check(&renames)?;
+    Ok(renames)
+}
+
+/// Checks that no two entries get the same name and that nothing outside of
+/// `renames` is overwritten. Nothing on disk is changed.
+pub fn check(renames: &[(PathBuf, PathBuf)]) -> Result<(), FilmanError> {
+    let sources = renames
+        .iter()
+        .map(|(source, _)| source)
+        .collect::<HashSet<_>>();
+    let mut targets = HashSet::new();
+    for (_, target) in renames {
+        if !targets.insert(target) {
+            return Err(FilmanError::CommandError(format!(
+                "Duplicate target {}",
+                target.display()
+            )));
+        }
+        if target.symlink_metadata().is_ok() && !sources.contains(target) {
+            return Err(FilmanError::FileOverwriteError(
+                target.display().to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Renames every (source, target) pair. The sources are first moved to
+/// temporary names so that swaps and cycles work. If anything fails the
+/// renames already done are rolled back.
+pub fn apply(renames: &[(PathBuf, PathBuf)]) -> Result<Operation, FilmanError> {
+    let mut steps = vec![];
+    for (index, (source, _)) in renames.iter().enumerate() {
+        steps.push((source.clone(), temporary_name(source, index)?));
+    }
+    for (index, (_, target)) in renames.iter().enumerate() {
+        steps.push((steps[index].1.clone(), target.clone()));
+    }
+
+    for (done, (from, to)) in steps.iter().enumerate() {
+        let result = if to.symlink_metadata().is_ok() {
+            Err(FilmanError::FileOverwriteError(to.display().to_string()))
+        } else {
+            fs::rename(from, to).map_err(|e| FilmanError::CommandError(e.to_string()))
+        };
+        if let Err(e) = result {
+            for (from, to) in steps[..done].iter().rev() {
+                let _ = fs::rename(to, from);
+            }
+            return Err(e);
+        }
+    }
+
+    Ok(Operation::Batch(
+        steps
+            .into_iter()
+            .map(|(from, to)| Operation::Rename { from, to })
+            .collect(),
+    ))
+}
+
+fn temporary_name(path: &Path, index: usize) -> Result<PathBuf, FilmanError> {
+    let name = path.file_name().ok_or(FilmanError::PathHasNoFilename)?;
+    Ok(path.with_file_name(format!(
+        ".{}.filman-rename-{}-{}",
+        name.to_string_lossy(),
+        std::process::id(),
+        index
+    )))
+}
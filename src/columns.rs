@@ -0,0 +1,130 @@
+use std::{
+    fs::{self, FileType, Metadata},
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::PathBuf,
+};
+
+use tui::layout::Constraint;
+
+use crate::{
+    config::Column,
+    permissions::{group_name, user_name},
+};
+
+impl Column {
+    pub fn header(&self) -> &'static str {
+        match self {
+            Column::Size => "Size",
+            Column::Modified => "Modified",
+            Column::Permissions => "Permissions",
+            Column::Owner => "Owner",
+            Column::Group => "Group",
+            Column::Inode => "Inode",
+            Column::Links => "Links",
+            Column::Type => "Type",
+        }
+    }
+
+    pub fn width(&self) -> Constraint {
+        match self {
+            Column::Size => Constraint::Length(10),
+            Column::Modified => Constraint::Length(16),
+            Column::Permissions => Constraint::Length(11),
+            Column::Owner | Column::Group => Constraint::Length(8),
+            Column::Inode => Constraint::Length(10),
+            Column::Links => Constraint::Length(5),
+            Column::Type => Constraint::Length(6),
+        }
+    }
+
+    /// The text of this column for `path`. Symlinks are described by
+    /// themselves, where they point is shown in the info column.
+    pub fn value(&self, path: &PathBuf) -> String {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return String::new();
+        };
+        match self {
+            Column::Size => human_bytes::human_bytes(metadata.len() as f64),
+            Column::Modified => format_time(metadata.mtime()),
+            Column::Permissions => permission_string(&metadata),
+            Column::Owner => {
+                user_name(metadata.uid()).unwrap_or_else(|| metadata.uid().to_string())
+            }
+            Column::Group => {
+                group_name(metadata.gid()).unwrap_or_else(|| metadata.gid().to_string())
+            }
+            Column::Inode => metadata.ino().to_string(),
+            Column::Links => metadata.nlink().to_string(),
+            Column::Type => type_name(metadata.file_type()).to_string(),
+        }
+    }
+}
+
+fn type_name(file_type: FileType) -> &'static str {
+    if file_type.is_symlink() {
+        "link"
+    } else if file_type.is_dir() {
+        "dir"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_block_device() {
+        "block"
+    } else if file_type.is_char_device() {
+        "char"
+    } else {
+        "file"
+    }
+}
+
+/// Permissions like `ls -l` shows them, e.g. `drwxr-xr-x`
+pub fn permission_string(metadata: &Metadata) -> String {
+    let file_type = metadata.file_type();
+    let mut ret = String::from(match type_name(file_type) {
+        "link" => 'l',
+        "dir" => 'd',
+        "fifo" => 'p',
+        "socket" => 's',
+        "block" => 'b',
+        "char" => 'c',
+        _ => '-',
+    });
+
+    let mode = metadata.mode();
+    // (read, write, execute, special bit, special char when executable)
+    let classes = [
+        (0o400, 0o200, 0o100, 0o4000, 's'),
+        (0o040, 0o020, 0o010, 0o2000, 's'),
+        (0o004, 0o002, 0o001, 0o1000, 't'),
+    ];
+    for (read, write, execute, special, special_char) in classes {
+        ret.push(if mode & read != 0 { 'r' } else { '-' });
+        ret.push(if mode & write != 0 { 'w' } else { '-' });
+        ret.push(match (mode & execute != 0, mode & special != 0) {
+            (true, true) => special_char,
+            (false, true) => special_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    ret
+}
+
+/// Seconds since the epoch as local `YYYY-MM-DD hh:mm`
+fn format_time(secs: i64) -> String {
+    let secs = secs as libc::time_t;
+
+    // SAFETY: localtime_r only writes to the tm struct we pass in
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
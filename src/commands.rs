@@ -1,6 +1,37 @@
-use std::{collections::HashSet, path::Path, process::Command};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::SystemTime,
+};
 
-use crate::{error::FilmanError, state::State};
+use regex::Regex;
+
+use crate::{
+    bulk_rename,
+    config::{ConflictPolicy, LaunchMode, SortKey},
+    draw::{resume_terminal, suspend_terminal},
+    error::FilmanError,
+    fileops::{
+        check_failed, copy_with_progress, move_recursive, move_with_progress, relative_path,
+        tree_summary, tree_summary_up_to, TreeSummary,
+    },
+    filter::Filter,
+    finder::Finder,
+    grep::{self, GrepResults},
+    input::Action,
+    jobs::JobOutcome,
+    journal::Operation,
+    open::find_handler,
+    paste::{PastePlan, Resolution},
+    path::Path as _,
+    permissions::{self, ModeChange, Owner},
+    rename_pattern::{self, Template},
+    state::{Mode, OutputPane, State, YankMode},
+    tokenize::{quote, tokenize, tokenize_with_variables, uses_variable},
+};
 
 // TODO: Maybe we should have two kinds of commands:
 //   Type A: Commands that uses the selected selected index such as the
@@ -8,47 +39,731 @@ use crate::{error::FilmanError, state::State};
 //   Type B: Commands that does not care what is selected but only acts 
 //     based on its arguments and pwd.
 
-// TODO: Rename should take two arguments and not worry about the selected state
+/// Entries counted for the summary in the confirmation of a delete
+const CONFIRM_COUNT_LIMIT: u64 = 10_000;
+
+/// Splits leading flags like `-f` off `args`. A `--` ends the flags.
+fn split_flags<'a>(
+    name: &str,
+    args: &'a [&'a str],
+    allowed: &[&str],
+) -> Result<(HashSet<&'a str>, &'a [&'a str]), FilmanError> {
+    let mut flags = HashSet::new();
+    let mut rest = args;
+    while let Some((&arg, tail)) = rest.split_first() {
+        if arg == "--" {
+            return Ok((flags, tail));
+        }
+        if !arg.starts_with('-') || arg == "-" {
+            break;
+        }
+        if !allowed.contains(&arg) {
+            return Err(FilmanError::CommandError(format!(
+                "Unknown flag {} for {}",
+                arg, name
+            )));
+        }
+        flags.insert(arg);
+        rest = tail;
+    }
+    Ok((flags, rest))
+}
+
+/// Makes sure every target can be written. Missing parent directories are
+/// created if `parents` is set and existing targets are returned as replaced
+/// if `force` is set.
+fn check_targets<'a>(
+    targets: impl Iterator<Item = &'a PathBuf>,
+    force: bool,
+    parents: bool,
+) -> Result<Vec<PathBuf>, FilmanError> {
+    let mut replaced = vec![];
+    let mut missing_parents = vec![];
+    for target in targets {
+        let parent = target.parent().ok_or(FilmanError::NoParentError)?;
+        if !parent.is_dir() {
+            if !parents || parent.symlink_metadata().is_ok() {
+                return Err(FilmanError::NotADirectory);
+            }
+            missing_parents.push(parent);
+        }
+
+        if target.symlink_metadata().is_ok() {
+            if !force {
+                return Err(FilmanError::FileOverwriteError(target.display().to_string()));
+            }
+            replaced.push(target.clone());
+        }
+    }
+
+    // Nothing is created until every target has been checked
+    for parent in missing_parents {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(replaced)
+}
+
+/// `:rename [-f] [-p] <from> <to>` with both paths relative to pwd
 fn rename(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
-    if args.len() != 1 {
+    let (flags, args) = split_flags(":rename", args, &["-f", "-p"])?;
+    let [from, to] = args else {
+        return Err(FilmanError::CommandError(
+            ":rename takes two arguments".into(),
+        ));
+    };
+
+    let from = state.pwd.join(from);
+    let to = state.pwd.join(to);
+    if from.symlink_metadata().is_err() {
+        return Err(FilmanError::CommandError(format!(
+            "{} does not exist",
+            from.display()
+        )));
+    }
+    if from == to {
+        return Ok(());
+    }
+    let replaced = check_targets([&to].into_iter(), flags.contains("-f"), flags.contains("-p"))?;
+
+    let mut operations = vec![];
+    let mut failed = vec![];
+    if let Some(path) = replaced.into_iter().next() {
+        let held_path = state.journal.reserve_held(&path)?;
+        move_recursive(&path, &held_path, &mut failed);
+        check_failed(failed)?;
+        operations.push(Operation::Delete {
+            held: vec![(path, held_path)],
+        });
+        failed = vec![];
+    }
+
+    move_recursive(&from, &to, &mut failed);
+    if to.symlink_metadata().is_ok() {
+        operations.push(Operation::Rename {
+            from: from.clone(),
+            to,
+        });
+    }
+    match operations.len() {
+        0 => {}
+        1 => state.journal.record(operations.pop().unwrap()),
+        _ => state.journal.record(Operation::Batch(operations)),
+    }
+    state.multi_select.remove(&from);
+    check_failed(failed)
+}
+
+/// `:move [-f] [-p] <src...> <dest>` moves the sources into the directory
+/// `dest`, or to the path `dest` if there is a single source
+fn move_entries(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (flags, args) = split_flags(":move", args, &["-f", "-p"])?;
+    let Some((dest, sources)) = args.split_last().filter(|(_, s)| !s.is_empty()) else {
+        return Err(FilmanError::CommandError(
+            ":move takes at least two arguments".into(),
+        ));
+    };
+    let (force, parents) = (flags.contains("-f"), flags.contains("-p"));
+
+    let dest = state.pwd.join(dest);
+    // With several sources a missing destination is created as a directory
+    // by check_targets
+    let into_dir =
+        dest.is_dir() || (parents && sources.len() > 1 && dest.symlink_metadata().is_err());
+
+    let mut pairs = vec![];
+    for source in sources {
+        let source = state.pwd.join(source);
+        if source.symlink_metadata().is_err() {
+            return Err(FilmanError::CommandError(format!(
+                "{} does not exist",
+                source.display()
+            )));
+        }
+        let target = if into_dir {
+            dest.join(source.file_name().ok_or(FilmanError::PathHasNoFilename)?)
+        } else if sources.len() == 1 {
+            dest.clone()
+        } else {
+            return Err(FilmanError::NotADirectory);
+        };
+        if target.starts_with(&source) {
+            return Err(FilmanError::CommandError(format!(
+                "Can not move {} into itself",
+                source.display()
+            )));
+        }
+        if pairs.iter().any(|(_, t)| *t == target) {
+            return Err(FilmanError::FileOverwriteError(target.display().to_string()));
+        }
+        pairs.push((source, target));
+    }
+    let replaced = check_targets(pairs.iter().map(|(_, target)| target), force, parents)?;
+
+    for (source, _) in &pairs {
+        state.multi_select.remove(source);
+        state.yanked.remove(source);
+    }
+    let description = format!("move {} entries", pairs.len());
+    spawn_transfer(description, YankMode::Cut, pairs, replaced, state)
+}
+
+/// Lets the user edit the names of the selected entries in `$EDITOR`
+fn bulk_rename_command(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":bulk_rename takes no arguments".into(),
+        ));
+    }
+
+    let mut paths = state.multi_select_or_selected()?.into_iter().collect::<Vec<_>>();
+    paths.sort();
+    let text = bulk_rename::names_text(&paths)?;
+
+    // The list lives in a directory only we can enter so that nobody can
+    // swap the file while the editor has it open
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or_default();
+    let dir = std::env::temp_dir()
+        .join(format!("filman-bulk-rename-{}-{}", std::process::id(), nanos));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let list = dir.join("names.txt");
+
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".into());
+    let edited = tokenize(&editor).and_then(|editor| {
+        let (program, editor_args) = editor
+            .split_first()
+            .ok_or_else(|| FilmanError::ShellCommandError("$EDITOR is empty".into()))?;
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&list)?
+            .write_all(text.as_bytes())?;
+        let mut command = Command::new(program);
+        command.args(editor_args).arg(&list);
+        run_interactive(command, state)?;
+        Ok(std::fs::read_to_string(&list)?)
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let renames = bulk_rename::parse_edited(&paths, &edited?)?;
+    if renames.is_empty() {
+        return Ok(());
+    }
+    let operation = bulk_rename::apply(&renames)?;
+    state.journal.record(operation);
+    for (source, _) in &renames {
+        state.multi_select.remove(source);
+    }
+    Ok(())
+}
+
+/// `:rename_pattern [-y] <regex> <replacement>` over the selection, or every
+/// entry in pwd if nothing is selected. Shows a preview first unless `-y` is
+/// given.
+fn rename_pattern_command(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (flags, args) = split_flags(":rename_pattern", args, &["-y"])?;
+    let [pattern, replacement] = args else {
+        return Err(FilmanError::CommandError(
+            ":rename_pattern takes two arguments".into(),
+        ));
+    };
+    let regex = Regex::new(pattern).map_err(|e| FilmanError::CommandParseError(e.to_string()))?;
+    let template = Template::parse(replacement)?;
+
+    let mut paths = if state.multi_select.is_empty() {
+        state.files_in_pwd()?
+    } else {
+        state.multiselected_in_pwd().into_iter().collect()
+    };
+    paths.sort();
+    let renames = rename_pattern::plan(&paths, &regex, &template)?;
+    if renames.is_empty() {
+        return Err(FilmanError::CommandError(format!(
+            "No names are changed by {}",
+            pattern
+        )));
+    }
+
+    if !flags.contains("-y") {
+        state.mode = Mode::RenamePreviewMode { renames };
+        return Ok(());
+    }
+    apply_renames(&renames, state)
+}
+
+/// Applies the renames shown in the preview, as confirmed by the user
+fn confirm_renames(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":confirm_renames takes no arguments".into(),
+        ));
+    }
+    let Mode::RenamePreviewMode { renames } = std::mem::replace(&mut state.mode, Mode::NormalMode)
+    else {
+        return Err(FilmanError::CommandError("No renames to confirm".into()));
+    };
+    // The entries may have changed since the preview was shown
+    bulk_rename::check(&renames)?;
+    apply_renames(&renames, state)
+}
+
+fn apply_renames(renames: &[(PathBuf, PathBuf)], state: &mut State) -> Result<(), FilmanError> {
+    let operation = bulk_rename::apply(renames)?;
+    state.journal.record(operation);
+    for (source, _) in renames {
+        state.multi_select.remove(source);
+    }
+    Ok(())
+}
+
+/// `:mkdir <path...>` creates the directories and any missing parents
+fn mkdir(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":mkdir takes at least one argument".into(),
+        ));
+    }
+
+    for arg in args {
+        let path = state.pwd.join(arg);
+        std::fs::create_dir_all(&path)?;
+        state.select_path(&path)?;
+    }
+    Ok(())
+}
+
+/// `:touch <path...>` creates empty files, or updates the modification time
+/// of existing ones
+fn touch(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":touch takes at least one argument".into(),
+        ));
+    }
+
+    for arg in args {
+        let path = state.pwd.join(arg);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        file.set_modified(SystemTime::now())?;
+        state.select_path(&path)?;
+    }
+    Ok(())
+}
+
+/// The paths named by `args`, or the selection if there are none
+fn paths_or_selection(args: &[&str], state: &State) -> Result<Vec<PathBuf>, FilmanError> {
+    let mut paths = match args {
+        [] => state.multi_select_or_selected()?.into_iter().collect(),
+        args => args.iter().map(|arg| state.pwd.join(arg)).collect::<Vec<_>>(),
+    };
+    paths.sort();
+    Ok(paths)
+}
+
+/// `:chmod [-R] <mode> [path...]` on the given paths or the selection
+fn chmod(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (flags, args) = split_flags(":chmod", args, &["-R"])?;
+    let Some((mode, args)) = args.split_first() else {
+        return Err(FilmanError::CommandError(":chmod takes a mode".into()));
+    };
+    let mode = mode.parse::<ModeChange>()?;
+    let paths = paths_or_selection(args, state)?;
+    permissions::chmod(&paths, &mode, flags.contains("-R"))
+}
+
+/// `:chown [-R] <user[:group]> [path...]` on the given paths or the selection
+fn chown(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (flags, args) = split_flags(":chown", args, &["-R"])?;
+    let Some((owner, args)) = args.split_first() else {
+        return Err(FilmanError::CommandError(":chown takes an owner".into()));
+    };
+    let owner = owner.parse::<Owner>()?;
+    let paths = paths_or_selection(args, state)?;
+    permissions::chown(&paths, owner, flags.contains("-R"))
+}
+
+/// `:sort <key|reverse|dirs_first>...` changes how pwd is sorted. `reverse`
+/// and `dirs_first` toggle. The cursor stays on the same entry.
+fn sort(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if args.is_empty() {
         return Err(FilmanError::CommandError(
-            ":rename takes one argument".into(),
+            ":sort takes at least one argument".into(),
         ));
     }
 
-    if let Some(old_path) = state.path_of_selected()? {
-        let new_name = state.pwd.join(args[0]);
-        std::fs::rename(old_path, new_name)
-            .map_err(|e| FilmanError::CommandError(e.to_string()))?;
+    let mut order = state.sort_order(&state.pwd);
+    for arg in args {
+        match *arg {
+            "reverse" => order.reverse = !order.reverse,
+            "dirs_first" => order.dirs_first = !order.dirs_first,
+            key => order.key = key.parse::<SortKey>()?,
+        }
+    }
+
+    let selected = state.path_of_selected()?;
+    state.sort_in_dir.insert(state.pwd.clone(), order);
+    state.keep_cursor_on(selected)
+}
+
+/// `:filter [filter]` narrows pwd to the matching names until the filter
+/// is cleared by running it without an argument
+fn filter(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let selected = state.path_of_selected()?;
+    match args {
+        [] => {
+            state.filters.remove(&state.pwd);
+        }
+        [filter] => {
+            state.filters.insert(state.pwd.clone(), Filter::parse(filter)?);
+        }
+        _ => {
+            return Err(FilmanError::CommandError(
+                ":filter takes at most one argument".into(),
+            ))
+        }
+    }
+    state.keep_cursor_on(selected)
+}
+
+/// `:search [-i] <pattern>` moves the cursor to the first match at or after
+/// it. `-i` is used while the search is typed and leaves out the history.
+/// Without a pattern the search is cleared.
+fn search(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (flags, args) = split_flags(":search", args, &["-i"])?;
+    let pattern = match args {
+        [] if flags.is_empty() => {
+            state.search = None;
+            return Ok(());
+        }
+        [pattern] => pattern,
+        _ => {
+            return Err(FilmanError::CommandError(
+                ":search takes at most one argument".into(),
+            ))
+        }
+    };
+    state.search = Some(Filter::parse(pattern)?);
+
+    let incremental = flags.contains("-i");
+    if !incremental {
+        state.search_history.push(pattern);
+    }
+    // Every step of a search being typed starts from the cursor before it,
+    // so that deleting characters goes back to the earlier matches
+    if let (true, Mode::SearchMode { previous_cursor, .. }) = (incremental, &state.mode) {
+        state.selected_in_pwd.insert(state.pwd.clone(), *previous_cursor);
+    }
+    match jump_to_match(state, true, true)? {
+        false if !incremental => Err(FilmanError::CommandError(format!(
+            "Pattern not found: {}",
+            pattern
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Puts back the search and the cursor from before the search was typed
+fn search_abort(state: &mut State) -> Result<(), FilmanError> {
+    let Mode::SearchMode {
+        previous_search,
+        previous_cursor,
+        ..
+    } = &state.mode
+    else {
+        return Err(FilmanError::CommandError("No search to abort".into()));
+    };
+    state.search = previous_search.clone();
+    state.selected_in_pwd.insert(state.pwd.clone(), *previous_cursor);
+    Ok(())
+}
+
+/// Moves to the next match of the latest search, wrapping around
+fn search_next(forward: bool, state: &mut State) -> Result<(), FilmanError> {
+    let Some(pattern) = state.search.clone() else {
+        return Err(FilmanError::CommandError("No previous search".into()));
+    };
+    if jump_to_match(state, forward, false)? {
         Ok(())
     } else {
-        Err(FilmanError::EmptyDirectory)
+        Err(FilmanError::CommandError(format!(
+            "Pattern not found: {}",
+            pattern
+        )))
     }
 }
 
+/// Moves the cursor to the closest match of `state.search` in the given
+/// direction, starting at the cursor if `inclusive` is set. Returns false if
+/// nothing matches.
+fn jump_to_match(state: &mut State, forward: bool, inclusive: bool) -> Result<bool, FilmanError> {
+    let Some(filter) = state.search.clone() else {
+        return Ok(false);
+    };
+    let files = state.files_in_pwd()?;
+    if files.is_empty() {
+        return Ok(false);
+    }
+
+    let cursor = state.selected_index_in_pwd();
+    let start = if inclusive { 0 } else { 1 };
+    let len = files.len();
+    let found = (start..=len)
+        .map(|offset| match forward {
+            true => (cursor + offset) % len,
+            false => (cursor + len * 2 - offset) % len,
+        })
+        .find(|&i| files[i].filename().is_ok_and(|name| filter.matches(name)));
+
+    if let Some(index) = found {
+        state.selected_in_pwd.insert(state.pwd.clone(), index);
+    }
+    Ok(found.is_some())
+}
+
+/// `:find` opens a popup that fuzzy finds paths below pwd
+fn find(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(":find takes no arguments".into()));
+    }
+    state.mode = Mode::FindMode(Finder::new(
+        state.pwd.clone(),
+        state.show_hidden,
+        state.respect_ignore,
+    ));
+    Ok(())
+}
+
+/// `:goto <path> [line]` moves to the directory `path` is in and puts the
+/// cursor on it. A filter that would hide it is cleared. With a line the
+/// preview starts there.
+fn goto(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (path, line) = match args {
+        [path] => (path, None),
+        [path, line] => (
+            path,
+            Some(line.parse::<usize>().map_err(|_| {
+                FilmanError::CommandParseError(format!("Invalid line number: {}", line))
+            })?),
+        ),
+        _ => {
+            return Err(FilmanError::CommandError(
+                ":goto takes a path and an optional line".into(),
+            ))
+        }
+    };
+    let path = state.pwd.join(path);
+    if std::fs::symlink_metadata(&path).is_err() {
+        return Err(FilmanError::CommandError(format!(
+            "{} does not exist",
+            path.display()
+        )));
+    }
+    let Some(dir) = path.parent() else {
+        return Err(FilmanError::NoParentError);
+    };
+    state.pwd = dir.to_path_buf();
+    state.filters.remove(&state.pwd);
+    state.select_path(&path)?;
+    state.preview_line = line.map(|line| (path, line));
+    Ok(())
+}
+
+/// `:grep <pattern>` lists the lines of the text files below pwd that match
+/// the regex `pattern`. The matches show up while the search runs.
+fn grep_command(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let [pattern] = args else {
+        return Err(FilmanError::CommandError(":grep takes one argument".into()));
+    };
+    let regex = grep::parse_pattern(pattern)?;
+    state.mode = Mode::GrepMode(GrepResults::new(
+        pattern.to_string(),
+        regex,
+        state.pwd.clone(),
+        state.show_hidden,
+    ));
+    Ok(())
+}
+
+/// Toggles listing dotfiles
+fn toggle_hidden(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":toggle_hidden takes no arguments".into(),
+        ));
+    }
+    let selected = state.path_of_selected()?;
+    state.show_hidden = !state.show_hidden;
+    state.keep_cursor_on(selected)
+}
+
+/// Toggles hiding the entries matched by `.gitignore` and `.ignore` files
+fn toggle_ignored(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":toggle_ignored takes no arguments".into(),
+        ));
+    }
+    let selected = state.path_of_selected()?;
+    state.respect_ignore = !state.respect_ignore;
+    state.ignore_rules.clear();
+    state.keep_cursor_on(selected)
+}
+
+/// `:delete [-r] <path...>`. Non-empty directories are only deleted with `-r`
+/// or after confirming.
 fn delete(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (flags, args) = split_flags(":delete", args, &["-r"])?;
+    let recursive = flags.contains("-r");
     if args.is_empty() {
         return Err(FilmanError::CommandError(
             ":delete takes at least one argument".into(),
         ));
     }
+
+    // Deleting non-empty directories has to be confirmed first
+    let paths = args.iter().map(|arg| state.pwd.join(arg)).collect::<Vec<_>>();
+    if !recursive && paths.iter().any(|path| is_non_empty_dir(path)) {
+        // Huge trees are not counted to the end so that the prompt shows up
+        // right away
+        let mut summary = TreeSummary::default();
+        let mut complete = true;
+        for path in &paths {
+            let limit = CONFIRM_COUNT_LIMIT.saturating_sub(summary.dirs + summary.files);
+            let (counted, counted_all) = tree_summary_up_to(path, limit)?;
+            summary += counted;
+            complete &= counted_all;
+        }
+        let at_least = if complete { "" } else { "at least " };
+        state.mode = Mode::ConfirmMode {
+            message: format!("delete {}{}? y/N", at_least, summary),
+            actions: vec![Action::Command(format!(
+                ":delete -r -- {}",
+                args.iter().map(|arg| quote(arg)).collect::<Vec<_>>().join(" ")
+            ))],
+        };
+        return Ok(());
+    }
+
+    // Deleted entries are moved to the holding area so that they can be restored
+    let mut held = vec![];
+    for path in paths {
+        // Remove from selection before deleting
+        if state.multi_select.contains(&path) {
+            state.multi_select.remove(&path);
+        }
+
+        let held_path = state.journal.reserve_held(&path)?;
+        held.push((path, held_path));
+    }
+
+    state.spawn_job(format!("delete {} entries", held.len()), move |progress| {
+        for (path, _) in &held {
+            progress.add_total(tree_summary(path).unwrap_or_default());
+        }
+
+        let mut failed = vec![];
+        for (path, held_path) in &held {
+            move_with_progress(path, held_path, progress, &mut failed);
+        }
+
+        held.retain(|(_, held_path)| held_path.symlink_metadata().is_ok());
+        JobOutcome {
+            operation: (!held.is_empty()).then_some(Operation::Delete { held }),
+            result: check_failed(failed),
+        }
+    });
+    Ok(())
+}
+
+fn is_non_empty_dir(path: &Path) -> bool {
+    !path.is_symlink()
+        && std::fs::read_dir(path)
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false)
+}
+
+/// `:trash <path...>`, journaled so that undoing restores the entries
+fn trash(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":trash takes at least one argument".into(),
+        ));
+    }
+    let mut trashed = vec![];
+    let mut errors = vec![];
     for arg in args {
         let path = state.pwd.join(arg);
 
-        // Remove from selection before deleting
+        // Remove from selection before trashing
         if state.multi_select.contains(&path) {
             state.multi_select.remove(&path);
         }
 
-        if path.is_dir() {
-            std::fs::remove_dir(path).map_err(|e| FilmanError::CommandError(e.to_string()))?;
-        } else {
-            std::fs::remove_file(path).map_err(|e| FilmanError::CommandError(e.to_string()))?;
+        match state.trash.put(&path) {
+            Ok(name) => trashed.push((path, name)),
+            Err(e) => errors.push(e),
         }
     }
+    if !trashed.is_empty() {
+        state.journal.record(Operation::Trash {
+            trash: state.trash.clone(),
+            trashed,
+        });
+    }
+    FilmanError::collect(errors)
+}
+
+fn trash_list(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":trash_list takes no arguments".into(),
+        ));
+    }
+
+    // The trash is listed by browsing its files directory
+    let files_dir = state.trash.files_dir();
+    std::fs::create_dir_all(&files_dir)?;
+    state.pwd = files_dir;
     Ok(())
 }
 
+fn trash_restore(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":trash_restore takes at least one argument".into(),
+        ));
+    }
+    for arg in args {
+        state.trash.restore(Path::new(arg))?;
+    }
+    Ok(())
+}
+
+/// `:trash_empty [-y]`, asks for confirmation unless `-y` is given
+fn trash_empty(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (flags, args) = split_flags(":trash_empty", args, &["-y"])?;
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":trash_empty takes no arguments".into(),
+        ));
+    }
+    if !flags.contains("-y") {
+        state.mode = Mode::ConfirmMode {
+            message: "permanently delete everything in the trash? y/N".into(),
+            actions: vec![Action::Command(":trash_empty -y".into())],
+        };
+        return Ok(());
+    }
+    state.journal.forget_trashed();
+    state.trash.empty()
+}
+
 fn yank(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
     if args.is_empty() {
         return Err(FilmanError::CommandError(
@@ -57,6 +772,25 @@ fn yank(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
     }
 
     state.yanked = HashSet::new();
+    state.yank_mode = YankMode::Copy;
+
+    for &arg in args.iter() {
+        let path = state.pwd.join(arg);
+        state.yanked.insert(path);
+    }
+
+    Ok(())
+}
+
+fn cut(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":cut takes at least one argument".into(),
+        ));
+    }
+
+    state.yanked = HashSet::new();
+    state.yank_mode = YankMode::Cut;
 
     for &arg in args.iter() {
         let path = state.pwd.join(arg);
@@ -67,35 +801,263 @@ fn yank(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
 }
 
 fn paste(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let mut policy = state.paste_policy;
+    for arg in args {
+        match arg.strip_prefix("--policy=") {
+            Some(name) => policy = name.parse()?,
+            None => {
+                return Err(FilmanError::CommandError(format!(
+                    "Unknown argument to :paste {}",
+                    arg
+                )))
+            }
+        }
+    }
+
+    // Every entry is checked for collisions before anything is pasted
+    let mut plan = PastePlan::new(&state.yanked, &state.pwd, state.yank_mode)?;
+    if plan.apply_policy(policy)? {
+        start_paste(plan, state)
+    } else {
+        state.mode = Mode::ConflictMode(plan);
+        Ok(())
+    }
+}
+
+/// `:paste_symlink [-r]` links the yanked entries into pwd, with relative
+/// link targets if `-r` is given
+fn paste_symlink(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let relative = match args {
+        [] => false,
+        ["-r"] => true,
+        _ => {
+            return Err(FilmanError::CommandError(
+                ":paste_symlink takes only an optional -r".into(),
+            ))
+        }
+    };
+
+    let pairs = link_pairs(state)?
+        .into_iter()
+        .map(|(source, link)| match relative {
+            true => (relative_path(&state.pwd, &source), link),
+            false => (source, link),
+        })
+        .collect();
+    create_links(pairs, true, state)
+}
+
+/// `:paste_hardlink` hard links the yanked files into pwd
+fn paste_hardlink(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
     if !args.is_empty() {
         return Err(FilmanError::CommandError(
-            ":paste takes not arguments".into(),
+            ":paste_hardlink takes no arguments".into(),
         ));
     }
 
-    use crate::path::Path;
+    let pairs = link_pairs(state)?;
+    create_links(pairs, false, state)
+}
 
-    let parent = &state.pwd;
-    for path in &state.yanked {
-        let filename = path.filename()?;
+/// Pairs every yanked entry with the link to create for it in pwd. Links
+/// never replace existing entries.
+fn link_pairs(state: &State) -> Result<Vec<(PathBuf, PathBuf)>, FilmanError> {
+    if state.yanked.is_empty() {
+        return Err(FilmanError::CommandError("Nothing is yanked".into()));
+    }
+    let mut plan = PastePlan::new(&state.yanked, &state.pwd, YankMode::Copy)?;
+    plan.apply_policy(ConflictPolicy::Abort)?;
+    Ok(plan.pairs)
+}
 
-        let files_in_pwd = state.files_in_pwd()?;
-        let filenames_in_pwd = files_in_pwd
-            .iter()
-            .map(|x| x.filename())
-            .collect::<Result<Vec<_>, FilmanError>>()?;
+/// Creates a link for every (target, link) pair and journals the ones that
+/// were made
+fn create_links(
+    mut pairs: Vec<(PathBuf, PathBuf)>,
+    symbolic: bool,
+    state: &mut State,
+) -> Result<(), FilmanError> {
+    let mut failed = vec![];
+    for (target, link) in &pairs {
+        let result = match symbolic {
+            true => std::os::unix::fs::symlink(target, link),
+            false => std::fs::hard_link(target, link),
+        };
+        if let Err(e) = result {
+            failed.push((link.clone(), e));
+        }
+    }
+
+    pairs.retain(|(_, link)| link.symlink_metadata().is_ok());
+    if let Some((_, link)) = pairs.last() {
+        state.select_path(link)?;
+    }
+    if !pairs.is_empty() {
+        state.journal.record(match symbolic {
+            true => Operation::Symlink { created: pairs },
+            false => Operation::Hardlink { created: pairs },
+        });
+    }
+    check_failed(failed)
+}
+
+fn resolve_conflict(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    let (resolution, all) = match args {
+        [resolution] => (*resolution, false),
+        [resolution, "--all"] => (*resolution, true),
+        _ => {
+            return Err(FilmanError::CommandError(
+                ":resolve_conflict takes a resolution and optionally --all".into(),
+            ))
+        }
+    };
+    let resolution = match resolution {
+        "skip" => Resolution::Skip,
+        "overwrite" => Resolution::Overwrite,
+        "rename" => Resolution::Rename,
+        _ => {
+            return Err(FilmanError::CommandError(format!(
+                "Unknown resolution {}",
+                resolution
+            )))
+        }
+    };
+
+    let Mode::ConflictMode(mut plan) = std::mem::replace(&mut state.mode, Mode::NormalMode) else {
+        return Err(FilmanError::CommandError(
+            "No paste conflict to resolve".into(),
+        ));
+    };
+    plan.resolve_next(resolution);
+    while all && !plan.conflicts.is_empty() {
+        plan.resolve_next(resolution);
+    }
+
+    if plan.conflicts.is_empty() {
+        start_paste(plan, state)
+    } else {
+        state.mode = Mode::ConflictMode(plan);
+        Ok(())
+    }
+}
+
+/// Pastes the planned entries in a background job
+fn start_paste(plan: PastePlan, state: &mut State) -> Result<(), FilmanError> {
+    let PastePlan {
+        yank_mode,
+        pairs,
+        replaced,
+        ..
+    } = plan;
+
+    // Cut entries are gone from their old location so they can only be pasted once
+    if yank_mode == YankMode::Cut {
+        for path in state.yanked.drain() {
+            state.multi_select.remove(&path);
+        }
+        state.yank_mode = YankMode::Copy;
+    }
+
+    let description = format!("paste {} entries", pairs.len());
+    spawn_transfer(description, yank_mode, pairs, replaced, state)
+}
+
+/// Copies or moves every (source, target) pair in a background job after
+/// moving the `replaced` entries out of the way
+fn spawn_transfer(
+    description: String,
+    yank_mode: YankMode,
+    mut pairs: Vec<(PathBuf, PathBuf)>,
+    replaced: Vec<PathBuf>,
+    state: &mut State,
+) -> Result<(), FilmanError> {
+    // Replaced entries are moved to the holding area so that undo can restore them
+    let mut held = vec![];
+    for path in replaced {
+        let held_path = state.journal.reserve_held(&path)?;
+        held.push((path, held_path));
+    }
+
+    state.spawn_job(description, move |progress| {
+        for (path, _) in &pairs {
+            progress.add_total(tree_summary(path).unwrap_or_default());
+        }
+
+        let mut failed = vec![];
+        for (path, held_path) in &held {
+            move_with_progress(path, held_path, progress, &mut failed);
+        }
+        held.retain(|(_, held_path)| held_path.symlink_metadata().is_ok());
 
-        if filenames_in_pwd.contains(&filename) {
-            return Err(FilmanError::FileOverwriteError(filename.into()));
+        // Nothing is pasted over entries that could not be moved away
+        if failed.is_empty() {
+            for (path, target) in &pairs {
+                match yank_mode {
+                    YankMode::Copy => copy_with_progress(path, target, progress, &mut failed),
+                    YankMode::Cut => move_with_progress(path, target, progress, &mut failed),
+                }
+            }
         }
 
-        std::fs::copy(path, parent.join(filename))
-            .map_err(|e| FilmanError::CommandError(e.to_string()))?;
+        // Also journal partial copies so that undo cleans them up, but only
+        // moves that have left their source since undo moves them back
+        pairs.retain(|(source, target)| {
+            target.symlink_metadata().is_ok()
+                && (yank_mode == YankMode::Copy || source.symlink_metadata().is_err())
+        });
+        let mut operations = vec![];
+        if !held.is_empty() {
+            operations.push(Operation::Delete { held });
+        }
+        if !pairs.is_empty() {
+            operations.push(match yank_mode {
+                YankMode::Copy => Operation::Copy { created: pairs },
+                YankMode::Cut => Operation::Move { moved: pairs },
+            });
+        }
+        let operation = match operations.len() {
+            0 => None,
+            1 => operations.pop(),
+            _ => Some(Operation::Batch(operations)),
+        };
+        JobOutcome {
+            operation,
+            result: check_failed(failed),
+        }
+    });
+    Ok(())
+}
+
+fn jobs(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(":jobs takes no arguments".into()));
     }
 
+    let text = state
+        .jobs
+        .iter()
+        .map(|job| format!("[{}] {}: {}", job.id, job.description, job.progress))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = if text.is_empty() {
+        "No running jobs".into()
+    } else {
+        text
+    };
+    state.mode = Mode::OutputMode(OutputPane::new(":jobs".into(), text));
     Ok(())
 }
 
+fn cancel(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if args.len() != 1 {
+        return Err(FilmanError::CommandError(":cancel takes one argument".into()));
+    }
+    let id = args[0]
+        .parse()
+        .map_err(|_| FilmanError::CommandError(format!("Invalid job id {}", args[0])))?;
+    state.jobs.cancel(id)
+}
+
 fn toggle_select(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
     if args.is_empty() {
         return Err(FilmanError::CommandError(
@@ -113,6 +1075,50 @@ fn toggle_select(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
     Ok(())
 }
 
+fn undo(state: &mut State) -> Result<(), FilmanError> {
+    state.journal.undo()
+}
+
+fn redo(state: &mut State) -> Result<(), FilmanError> {
+    state.journal.redo()
+}
+
+fn shell(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if let Some((&program, args)) = args.split_first() {
+        let mut command = Command::new(program);
+        command.args(args);
+        run_interactive(command, state)
+    } else {
+        Err(FilmanError::CommandError(
+            ":shell takes at least one argument".into(),
+        ))
+    }
+}
+
+fn open_shell(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if !args.is_empty() {
+        return Err(FilmanError::CommandError(
+            ":open_shell takes no arguments".into(),
+        ));
+    }
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".into());
+    run_interactive(Command::new(shell), state)
+}
+
+/// Runs `command` in pwd with the real terminal, suspending the TUI until it exits
+fn run_interactive(mut command: Command, state: &mut State) -> Result<(), FilmanError> {
+    suspend_terminal()?;
+    let status = command.current_dir(&state.pwd).status();
+    resume_terminal()?;
+    state.full_redraw = true;
+
+    let status = status.map_err(|e| FilmanError::ShellCommandError(e.to_string()))?;
+    if !status.success() {
+        return Err(FilmanError::ShellCommandError(status.to_string()));
+    }
+    Ok(())
+}
+
 fn clear_selection(state: &mut State) {
     state.multi_select.clear()
 }
@@ -162,7 +1168,50 @@ fn cursor_descend(state: &mut State) -> Result<(), FilmanError> {
         state.pwd = new_pwd;
         Ok(())
     } else {
-        Err(FilmanError::NotADirectory)
+        open_file(&new_pwd, state)
+    }
+}
+
+fn open(args: &[&str], state: &mut State) -> Result<(), FilmanError> {
+    if args.len() != 1 {
+        return Err(FilmanError::CommandError(":open takes one argument".into()));
+    }
+    let path = state.pwd.join(args[0]);
+    open_file(&path, state)
+}
+
+/// Opens `path` with the handler configured for its type
+fn open_file(path: &Path, state: &mut State) -> Result<(), FilmanError> {
+    let handler = find_handler(&state.open_handlers, path);
+
+    let mut variables = HashMap::new();
+    variables.insert("f", vec![path.to_string_lossy().into_owned()]);
+    let mut tokens = tokenize_with_variables(&handler.command, &variables)?;
+    if !uses_variable(&handler.command, "f")? {
+        tokens.push(path.to_string_lossy().into_owned());
+    }
+
+    let (program, args) = tokens
+        .split_first()
+        .ok_or_else(|| FilmanError::CommandError("Empty open command".into()))?;
+    let mut command = Command::new(program);
+    command.args(args);
+
+    match handler.mode {
+        LaunchMode::Blocking => run_interactive(command, state),
+        LaunchMode::Detached => {
+            let mut child = command
+                .current_dir(&state.pwd)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .map_err(|e| FilmanError::ShellCommandError(e.to_string()))?;
+
+            // Reap the child when it exits so it does not linger as a zombie
+            std::thread::spawn(move || child.wait());
+            Ok(())
+        }
     }
 }
 
@@ -189,20 +1238,55 @@ fn cursor_ascend(state: &mut State) -> Result<(), FilmanError> {
 }
 
 pub fn execute_command(cmd: &str, state: &mut State) -> Result<(), FilmanError> {
-    let split_cmd = cmd.split(' ').collect::<Vec<&str>>();
+    let tokens = tokenize(cmd)?;
+    let split_cmd = tokens.iter().map(String::as_str).collect::<Vec<&str>>();
 
     if let Some((&cmd_name, args)) = split_cmd.split_first() {
         match cmd_name {
             ":rename" => rename(args, state)?,
+            ":move" => move_entries(args, state)?,
+            ":bulk_rename" => bulk_rename_command(args, state)?,
+            ":rename_pattern" => rename_pattern_command(args, state)?,
+            ":mkdir" => mkdir(args, state)?,
+            ":sort" => sort(args, state)?,
+            ":filter" => filter(args, state)?,
+            ":search" => search(args, state)?,
+            ":search_abort" => search_abort(state)?,
+            ":search_next" => search_next(true, state)?,
+            ":search_prev" => search_next(false, state)?,
+            ":find" => find(args, state)?,
+            ":goto" => goto(args, state)?,
+            ":grep" => grep_command(args, state)?,
+            ":toggle_hidden" => toggle_hidden(args, state)?,
+            ":toggle_ignored" => toggle_ignored(args, state)?,
+            ":chmod" => chmod(args, state)?,
+            ":chown" => chown(args, state)?,
+            ":touch" => touch(args, state)?,
             ":delete" => delete(args, state)?,
+            ":trash" => trash(args, state)?,
+            ":trash_list" => trash_list(args, state)?,
+            ":trash_restore" => trash_restore(args, state)?,
+            ":trash_empty" => trash_empty(args, state)?,
             ":yank" => yank(args, state)?,
+            ":cut" => cut(args, state)?,
             ":paste" => paste(args, state)?,
+            ":paste_symlink" => paste_symlink(args, state)?,
+            ":paste_hardlink" => paste_hardlink(args, state)?,
+            ":resolve_conflict" => resolve_conflict(args, state)?,
+            ":confirm_renames" => confirm_renames(args, state)?,
+            ":jobs" => jobs(args, state)?,
+            ":cancel" => cancel(args, state)?,
             ":toggle_select" => toggle_select(args, state)?,
             ":cursor_down" => cursor_down(state)?,
             ":cursor_up" => cursor_up(state)?,
             ":cursor_ascend" => cursor_ascend(state)?,
             ":cursor_descend" => cursor_descend(state)?,
+            ":open" => open(args, state)?,
             ":clear_selection" => clear_selection(state),
+            ":shell" => shell(args, state)?,
+            ":open_shell" => open_shell(args, state)?,
+            ":undo" => undo(state)?,
+            ":redo" => redo(state)?,
             _ => {
                 return Err(FilmanError::CommandError(format!(
                     "Unrecognized command {cmd}"
@@ -218,16 +1302,104 @@ pub fn execute_command(cmd: &str, state: &mut State) -> Result<(), FilmanError>
     Ok(())
 }
 
-pub fn execute_shell_command(cmd: &str, pwd: &Path) -> Result<(), FilmanError> {
-    let split_cmd = cmd.split(' ').collect::<Vec<&str>>();
+/// Values for the placeholders that can be used in shell commands
+fn shell_variables(state: &State) -> Result<HashMap<&'static str, Vec<String>>, FilmanError> {
+    let to_strings = |paths: HashSet<PathBuf>| {
+        let mut strings = paths
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        strings.sort();
+        strings
+    };
+
+    let mut variables = HashMap::new();
+    variables.insert(
+        "f",
+        state
+            .path_of_selected()?
+            .map(|p| vec![p.to_string_lossy().into_owned()])
+            .unwrap_or_default(),
+    );
+    variables.insert(
+        "fs",
+        to_strings(state.multi_select_or_selected().unwrap_or_default()),
+    );
+    variables.insert("d", vec![state.pwd.to_string_lossy().into_owned()]);
+    variables.insert("y", to_strings(state.yanked.clone()));
+    Ok(variables)
+}
+
+/// What a shell command wrote to stdout and stderr, and whether it failed
+pub struct ShellOutput {
+    pub output: String,
+    /// A `ShellCommandError` with the stderr text when the exit status isn't 0
+    pub result: Result<(), FilmanError>,
+}
+
+/// Runs a shell command in pwd and returns what it wrote to stdout and stderr,
+/// failing with the stderr text when the command does.
+pub fn execute_shell_command(cmd: &str, state: &State) -> Result<String, FilmanError> {
+    let ran = run_shell_command(cmd, state)?;
+    ran.result.map(|()| ran.output)
+}
+
+/// Runs a shell command in pwd, keeping its output whether it fails or not.
+/// `$f`, `$fs`, `$d` and `$y` are replaced with the selected file, the
+/// selection, pwd and the yanked files. They are also passed to the command
+/// as `FILMAN_*` environment variables.
+pub fn run_shell_command(cmd: &str, state: &State) -> Result<ShellOutput, FilmanError> {
+    let variables = shell_variables(state)?;
+    let tokens = tokenize_with_variables(cmd, &variables)?;
+    let split_cmd = tokens.iter().map(String::as_str).collect::<Vec<&str>>();
     if let Some((&cmd_name, args)) = split_cmd.split_first() {
         let mut chars = cmd_name.chars();
         chars.next();
-        Command::new(chars.as_str())
-            .current_dir(pwd)
+
+        // Single paths are passed as they are. The paths of the lists
+        // (FILMAN_SELECTION and FILMAN_YANKED) are quoted and joined with
+        // spaces so that the shell can split them.
+        let env = [
+            ("FILMAN_SELECTED", "f", false),
+            ("FILMAN_SELECTION", "fs", true),
+            ("FILMAN_PWD", "d", false),
+            ("FILMAN_YANKED", "y", true),
+        ]
+        .into_iter()
+        .map(|(env_name, name, is_list)| {
+            let value = match is_list {
+                true => variables[name].iter().map(|v| quote(v)).collect::<Vec<_>>().join(" "),
+                false => variables[name].concat(),
+            };
+            (env_name, value)
+        });
+
+        let output = Command::new(chars.as_str())
+            .current_dir(&state.pwd)
             .args(args)
+            .envs(env)
             .output()
             .map_err(|e| FilmanError::ShellCommandError(e.to_string()))?;
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let result = match output.status.success() {
+            true => Ok(()),
+            false if stderr.trim().is_empty() => {
+                Err(FilmanError::ShellCommandError(output.status.to_string()))
+            }
+            false => Err(FilmanError::ShellCommandError(format!(
+                "{}: {}",
+                output.status,
+                stderr.trim()
+            ))),
+        };
+        return Ok(ShellOutput {
+            output: String::from_utf8_lossy(&output.stdout).into_owned() + &stderr,
+            result,
+        });
     }
-    Ok(())
+    Ok(ShellOutput {
+        output: String::new(),
+        result: Ok(()),
+    })
 }
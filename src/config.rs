@@ -1,15 +1,149 @@
-use std::{collections::HashMap, path::PathBuf, fs::File};
+use std::{collections::HashMap, path::PathBuf, fs::File, str::FromStr};
 
+use lazy_static::lazy_static;
+
+use crate::error::FilmanError;
 use crate::input::Action;
 
+lazy_static! {
+    /// `config.json`, read once when it is first used
+    pub static ref CONFIG: Config = Config::new("config.json".into());
+}
+
 #[derive(serde::Deserialize, Debug, Clone)]
 struct KeyConfig {
     simple: HashMap<char, String>
 }
 
+/// How a program opening a file is run
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LaunchMode {
+    /// Takes over the terminal until it exits, like an editor
+    Blocking,
+    /// Runs in the background, like an image viewer
+    #[default]
+    Detached,
+}
+
+/// A program used by `:open` for files with the given extensions or MIME
+/// types. `$f` in the command is replaced with the file, otherwise the file
+/// is passed as the last argument.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct OpenHandler {
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    #[serde(default)]
+    pub mime: Vec<String>,
+    pub command: String,
+    #[serde(default)]
+    pub mode: LaunchMode,
+}
+
+/// What `:paste` does when an entry with the same name already exists
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Refuse to paste anything
+    #[default]
+    Abort,
+    Skip,
+    Overwrite,
+    /// Paste with a suffix like `foo (1).txt`
+    Rename,
+    OverwriteIfNewer,
+    /// Ask for every conflicting entry
+    Ask,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = FilmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.into()))
+            .map_err(|_| FilmanError::CommandParseError(format!("Unknown paste policy {}", s)))
+    }
+}
+
+/// A metadata column of the Files table
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Column {
+    Size,
+    Modified,
+    /// Like `drwxr-xr-x`
+    Permissions,
+    Owner,
+    Group,
+    Inode,
+    /// Number of hard links
+    Links,
+    Type,
+}
+
+/// What directory listings are sorted by
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// Natural order, so `file2` comes before `file10`
+    #[default]
+    Name,
+    Size,
+    Modified,
+    Extension,
+    Type,
+}
+
+impl FromStr for SortKey {
+    type Err = FilmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.into()))
+            .map_err(|_| FilmanError::CommandParseError(format!("Unknown sort key {}", s)))
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct SortOrder {
+    pub key: SortKey,
+    pub dirs_first: bool,
+    pub reverse: bool,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder {
+            key: SortKey::Name,
+            dirs_first: true,
+            reverse: false,
+        }
+    }
+}
+
+fn default_columns() -> Vec<Column> {
+    vec![Column::Size]
+}
+
+fn default_show_hidden() -> bool {
+    true
+}
+
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct Config {
-    keys: KeyConfig
+    keys: KeyConfig,
+    #[serde(default)]
+    open: Vec<OpenHandler>,
+    #[serde(default)]
+    paste_policy: ConflictPolicy,
+    #[serde(default = "default_columns")]
+    columns: Vec<Column>,
+    #[serde(default)]
+    sort: SortOrder,
+    #[serde(default = "default_show_hidden")]
+    show_hidden: bool,
+    #[serde(default)]
+    respect_ignore: bool,
 }
 
 
@@ -24,5 +158,28 @@ impl Config {
             (*k, vec![Action::Command(v.to_string())])
         }).collect()
     }
-}
 
+    pub fn open_handlers(&self) -> Vec<OpenHandler> {
+        self.open.clone()
+    }
+
+    pub fn paste_policy(&self) -> ConflictPolicy {
+        self.paste_policy
+    }
+
+    pub fn columns(&self) -> Vec<Column> {
+        self.columns.clone()
+    }
+
+    pub fn sort(&self) -> SortOrder {
+        self.sort
+    }
+
+    pub fn show_hidden(&self) -> bool {
+        self.show_hidden
+    }
+
+    pub fn respect_ignore(&self) -> bool {
+        self.respect_ignore
+    }
+}
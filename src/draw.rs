@@ -1,36 +1,71 @@
-use crossterm::terminal::enable_raw_mode;
-use crossterm::{event::EnableMouseCapture, execute, terminal::EnterAlternateScreen};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, LeaveAlternateScreen};
+use crossterm::{
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute,
+    terminal::EnterAlternateScreen,
+};
 use std::collections::HashSet;
 use std::io::Stdout;
 use std::io::{self, stdout};
-use tui::layout::{Alignment, Constraint, Layout};
+use std::path::PathBuf;
+use tui::layout::{Alignment, Constraint, Layout, Rect};
 use tui::style::{Color, Modifier, Style};
 use tui::text::{Span, Spans};
-use tui::widgets::{Cell, List, ListItem, ListState, Paragraph, Row, Table, TableState, Wrap};
+use tui::widgets::{Cell, Clear, List, ListItem, ListState, Paragraph, Row, Table, TableState, Wrap};
 use tui::{
     backend::CrosstermBackend,
     widgets::{Block, Borders},
     Terminal,
 };
 
+use crate::config::Column;
 use crate::error::FilmanError;
+use crate::grep::GrepMatch;
 use crate::path::Path;
 use crate::state::Mode;
+use crate::state::OutputPane;
 use crate::state::State;
+use crate::state::YankMode;
 
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub struct DirectoryEntry {
     name: String,
+    columns: Vec<String>,
+    /// Where a symlink points, empty for other entries
     info: String,
+    dangling: bool,
+}
+
+/// A result of the finder with the indices of the matched characters
+pub struct FinderRow {
+    path: String,
+    matched: Vec<usize>,
+    selected: bool,
+}
+
+pub struct FinderView {
+    title: String,
+    rows: Vec<FinderRow>,
+    cursor: Option<usize>,
+}
+
+/// The matches of a grep, shown as `file:line: snippet`
+pub struct GrepView<'a> {
+    title: String,
+    matches: &'a [GrepMatch],
+    cursor: usize,
 }
 
 // TODO: Don't leak things like filenames, selected or error_message into this interface
 pub struct RenderState<'a> {
     pub files_in_pwd: Vec<DirectoryEntry>,
+    pub columns: &'a [Column],
     pub selected_in_pwd: Option<usize>,
 
     pub multi_select: HashSet<String>,
+    pub search_matches: HashSet<String>,
     pub yanked: HashSet<String>,
+    pub cut: HashSet<String>,
 
     pub files_in_parent: Vec<String>,
     pub selected_in_parent: Option<usize>,
@@ -38,6 +73,11 @@ pub struct RenderState<'a> {
     pub preview: &'a str,
 
     pub command: Option<String>,
+    pub jobs: Option<String>,
+    pub output: Option<&'a OutputPane>,
+    pub rename_preview: Option<Vec<(String, String)>>,
+    pub finder: Option<FinderView>,
+    pub grep: Option<GrepView<'a>>,
     pub error_message: Option<&'a str>,
 }
 
@@ -48,7 +88,78 @@ impl<'a> TryFrom<&'a State> for RenderState<'a> {
         let command = match &other.mode {
             Mode::NormalMode => None,
             Mode::CommandMode(pr) => Some(pr.result().to_string()),
+            Mode::FilterMode(pr) => Some(format!("filter: {}", pr.result())),
+            Mode::SearchMode { reader, .. } => Some(format!("/{}", reader.result())),
+            Mode::FindMode(finder) => Some(format!(
+                "find: {} (Tab selects, Enter jumps)",
+                finder.reader.result()
+            )),
             Mode::ShellCommandMode(pr) => Some(pr.result().to_string()),
+            Mode::ConfirmMode { message, .. } => Some(message.clone()),
+            Mode::RenamePreviewMode { renames, .. } => {
+                Some(format!("rename {} entries? y/N", renames.len()))
+            }
+            Mode::GrepMode(_) => Some("grep: j/k to move, Enter jumps, Esc closes".into()),
+            Mode::OutputMode(_) => None,
+            Mode::ConflictMode(plan) => plan.conflicts.front().map(|(_, target)| {
+                format!(
+                    "{} exists: [o]verwrite, [s]kip, [r]ename (capital for all), Esc to abort",
+                    target.display()
+                )
+            }),
+        };
+        let output = match &other.mode {
+            Mode::OutputMode(pane) => Some(pane),
+            _ => None,
+        };
+        let rename_preview = match &other.mode {
+            Mode::RenamePreviewMode { renames, .. } => Some(
+                renames
+                    .iter()
+                    .map(|(old, new)| {
+                        let name = |p: &PathBuf| {
+                            p.file_name().unwrap_or_default().to_string_lossy().into_owned()
+                        };
+                        (name(old), name(new))
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+        let finder = match &other.mode {
+            Mode::FindMode(finder) => Some(FinderView {
+                title: format!(
+                    "Find {}/{}{}",
+                    finder.results().len(),
+                    finder.walked(),
+                    if finder.is_walking() { " …" } else { "" }
+                ),
+                rows: finder
+                    .results()
+                    .iter()
+                    .map(|result| FinderRow {
+                        path: result.path.to_string_lossy().into_owned(),
+                        matched: result.matched.clone(),
+                        selected: other.multi_select.contains(&finder.root.join(&result.path)),
+                    })
+                    .collect(),
+                cursor: (!finder.results().is_empty()).then_some(finder.cursor),
+            }),
+            _ => None,
+        };
+
+        let grep = match &other.mode {
+            Mode::GrepMode(results) => Some(GrepView {
+                title: format!(
+                    "grep {} ({} matches{})",
+                    results.pattern,
+                    results.matches.len(),
+                    if results.is_searching() { ", searching" } else { "" }
+                ),
+                matches: &results.matches,
+                cursor: results.cursor,
+            }),
+            _ => None,
         };
 
         let files_in_pwd = other
@@ -57,7 +168,12 @@ impl<'a> TryFrom<&'a State> for RenderState<'a> {
             .map(|x| {
                 Ok(DirectoryEntry {
                     name: x.filename()?.to_string(),
-                    info: human_bytes::human_bytes(x.size().unwrap_or(0) as f64),
+                    columns: other.columns.iter().map(|c| c.value(x)).collect(),
+                    info: x
+                        .link_target()
+                        .map(|target| format!("-> {}", target.display()))
+                        .unwrap_or_default(),
+                    dangling: x.is_dangling(),
                 })
             })
             .collect::<Result<Vec<_>, FilmanError>>()?;
@@ -78,24 +194,58 @@ impl<'a> TryFrom<&'a State> for RenderState<'a> {
             .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
             .collect();
 
-        let yanked = other
+        let search_matches = files_in_pwd
+            .iter()
+            .filter(|x| other.search.as_ref().is_some_and(|s| s.matches(&x.name)))
+            .map(|x| x.name.clone())
+            .collect();
+
+        let yanked_in_pwd: HashSet<String> = other
             .yanked
             .iter()
             .filter(|p| p.parent() == Some(&other.pwd))
             .map(|p| p.file_name().unwrap().to_str().unwrap().to_string())
             .collect();
+        let (yanked, cut) = match other.yank_mode {
+            YankMode::Copy => (yanked_in_pwd, HashSet::new()),
+            YankMode::Cut => (HashSet::new(), yanked_in_pwd),
+        };
+
+        let jobs = other
+            .jobs
+            .iter()
+            .map(|job| format!("[{}] {}: {}", job.id, job.description, job.progress))
+            .collect::<Vec<_>>();
+        let jobs = (!jobs.is_empty()).then(|| jobs.join(" | "));
+
+        // An active filter is shown when there is nothing else to show
+        let command = command.or_else(|| {
+            other
+                .filters
+                .get(&other.pwd)
+                .filter(|_| jobs.is_none())
+                .map(|filter| format!("filter: {} (f to edit, Esc in the prompt clears)", filter))
+        });
 
         let preview = other.file_contents.as_deref().unwrap_or("Binary file");
         let error_message = other.error_message.as_deref();
 
         Ok(RenderState {
             yanked,
+            cut,
             files_in_pwd,
+            columns: &other.columns,
             selected_in_pwd,
             files_in_parent,
             selected_in_parent,
             command,
+            jobs,
+            output,
+            rename_preview,
+            finder,
+            grep,
             multi_select,
+            search_matches,
             preview,
             error_message,
         })
@@ -103,18 +253,30 @@ impl<'a> TryFrom<&'a State> for RenderState<'a> {
 }
 
 pub fn create_terminal() -> Result<Terminal<CrosstermBackend<Stdout>>, io::Error> {
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
+    resume_terminal()?;
+    let backend = CrosstermBackend::new(io::stdout());
     let terminal = Terminal::new(backend)?;
     Ok(terminal)
 }
 
+/// Gives the terminal back to the shell so that an interactive child process
+/// can use it. The inverse of `resume_terminal`.
+pub fn suspend_terminal() -> Result<(), io::Error> {
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture)
+}
+
+/// Enters raw mode and the alternate screen
+pub fn resume_terminal() -> Result<(), io::Error> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)
+}
+
 fn format_file(
     file: &DirectoryEntry,
     selected: bool,
     yanked: bool,
+    cut: bool,
     multi_selected: bool,
 ) -> String {
     let mut ret = String::new();
@@ -125,6 +287,8 @@ fn format_file(
 
     if yanked {
         ret += "Y";
+    } else if cut {
+        ret += "X";
     } else {
         ret += " ";
     }
@@ -139,6 +303,33 @@ fn format_file(
     ret
 }
 
+/// A rectangle taking up the given percentages of `area`, centered in it
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(tui::layout::Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage((100 - percent_y) / 2),
+                Constraint::Percentage(percent_y),
+                Constraint::Percentage((100 - percent_y) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    Layout::default()
+        .direction(tui::layout::Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Percentage((100 - percent_x) / 2),
+                Constraint::Percentage(percent_x),
+                Constraint::Percentage((100 - percent_x) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(vertical[1])[1]
+}
+
 pub fn draw(
     state: &RenderState,
     terminal: &mut Terminal<CrosstermBackend<Stdout>>,
@@ -180,7 +371,19 @@ pub fn draw(
             .block(Block::default().title("Preview").borders(Borders::ALL))
             .wrap(Wrap { trim: false });
 
-        // Files table
+        // Files table, the name column takes the space the others leave.
+        // The info column is only there when a symlink is listed
+        let show_info = state.files_in_pwd.iter().any(|x| !x.info.is_empty());
+        let mut widths = vec![Constraint::Min(10)];
+        widths.extend(state.columns.iter().map(Column::width));
+        let mut header = vec!["Name"];
+        header.extend(state.columns.iter().map(Column::header));
+        if show_info {
+            widths.push(Constraint::Percentage(30));
+            header.push("Info");
+        }
+        let header = Row::new(header).style(Style::default().add_modifier(Modifier::BOLD));
+
         let table = Table::new(
             state
                 .files_in_pwd
@@ -191,17 +394,31 @@ pub fn draw(
                         &x,
                         false,
                         state.yanked.contains(&x.name),
+                        state.cut.contains(&x.name),
                         state.multi_select.contains(&x.name),
                     );
 
-                    Row::new(vec![Cell::from(formatted), Cell::from(x.info)])
-                        .style(Style::default())
+                    // Symlinks pointing nowhere and search matches stand out
+                    let style = if x.dangling {
+                        Style::default().fg(Color::Red).add_modifier(Modifier::CROSSED_OUT)
+                    } else if state.search_matches.contains(&x.name) {
+                        Style::default().fg(Color::Yellow)
+                    } else {
+                        Style::default()
+                    };
+                    let mut cells = vec![Cell::from(formatted)];
+                    cells.extend(x.columns.into_iter().map(Cell::from));
+                    if show_info {
+                        cells.push(Cell::from(x.info));
+                    }
+                    Row::new(cells).style(style)
                 })
                 .collect::<Vec<_>>(),
         )
         .style(Style::default().fg(Color::White))
+        .header(header)
         .block(Block::default().title("Files").borders(Borders::ALL))
-        .widths(&[Constraint::Percentage(80), Constraint::Percentage(20)])
+        .widths(&widths)
         .column_spacing(1)
         .highlight_style(Style::default().add_modifier(Modifier::BOLD))
         .highlight_symbol(">>");
@@ -210,13 +427,71 @@ pub fn draw(
         let mut files_state = TableState::default();
         files_state.select(state.selected_in_pwd);
 
+        // A pattern rename waiting for confirmation replaces the files
+        let table = match &state.rename_preview {
+            Some(renames) => {
+                files_state.select(None);
+                Table::new(
+                    renames
+                        .iter()
+                        .map(|(old, new)| {
+                            Row::new(vec![
+                                Cell::from(old.clone()),
+                                Cell::from("→"),
+                                Cell::from(new.clone()),
+                            ])
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .style(Style::default().fg(Color::White))
+                .block(Block::default().title("Rename preview").borders(Borders::ALL))
+                .widths(&[
+                    Constraint::Percentage(45),
+                    Constraint::Length(1),
+                    Constraint::Percentage(45),
+                ])
+                .column_spacing(1)
+            }
+            None => table,
+        };
+
+        // Grep matches replace the files too, the preview follows the cursor.
+        // Only the rows that fit are formatted, scrolled to keep the cursor
+        let table = match &state.grep {
+            Some(grep) => {
+                let height = main_window_rects[1].height.saturating_sub(2).max(1) as usize;
+                let first = grep.cursor.saturating_sub(height - 1);
+                files_state.select(Some(grep.cursor - first));
+                Table::new(
+                    grep.matches
+                        .iter()
+                        .skip(first)
+                        .take(height)
+                        .map(|m| {
+                            let row = format!("{}:{}: {}", m.path.display(), m.line, m.snippet);
+                            Row::new(vec![Cell::from(row)])
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .style(Style::default().fg(Color::White))
+                .block(Block::default().title(grep.title.as_str()).borders(Borders::ALL))
+                .widths(&[Constraint::Percentage(100)])
+                .highlight_style(Style::default().add_modifier(Modifier::BOLD))
+                .highlight_symbol(">>")
+            }
+            None => table,
+        };
+
         let mut parents_state = ListState::default();
         parents_state.select(state.selected_in_parent);
 
         // Command window
-        let command_window_string = state
-            .error_message
-            .unwrap_or(state.command.as_deref().unwrap_or(""));
+        let command_window_string = state.error_message.unwrap_or(
+            state
+                .command
+                .as_deref()
+                .unwrap_or(state.jobs.as_deref().unwrap_or("")),
+        );
 
         let command_window_text = vec![Spans::from(vec![Span::raw(command_window_string)])];
         let command_window = Paragraph::new(command_window_text)
@@ -228,6 +503,48 @@ pub fn draw(
         f.render_stateful_widget(table, main_window_rects[1], &mut files_state);
         f.render_widget(preview, main_window_rects[2]);
         f.render_widget(command_window, vertical_rects[1]);
+
+        // Shell command output popup
+        if let Some(output) = state.output {
+            let popup = Paragraph::new(output.text.as_str())
+                .block(Block::default().title(output.title.as_str()).borders(Borders::ALL))
+                .scroll((output.scroll, 0));
+            let popup_rect = centered_rect(80, 80, vertical_rects[0]);
+            f.render_widget(Clear, popup_rect);
+            f.render_widget(popup, popup_rect);
+        }
+
+        // Finder popup with the matched characters highlighted
+        if let Some(finder) = &state.finder {
+            let items = finder
+                .rows
+                .iter()
+                .map(|row| {
+                    let mut spans = vec![Span::raw(if row.selected { "S " } else { "  " })];
+                    spans.extend(row.path.chars().enumerate().map(|(i, c)| {
+                        if row.matched.contains(&i) {
+                            Span::styled(
+                                c.to_string(),
+                                Style::default()
+                                    .fg(Color::Yellow)
+                                    .add_modifier(Modifier::BOLD),
+                            )
+                        } else {
+                            Span::raw(c.to_string())
+                        }
+                    }));
+                    ListItem::new(Spans::from(spans))
+                })
+                .collect::<Vec<_>>();
+            let list = List::new(items)
+                .block(Block::default().title(finder.title.as_str()).borders(Borders::ALL))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            let mut list_state = ListState::default();
+            list_state.select(finder.cursor);
+            let popup_rect = centered_rect(80, 80, vertical_rects[0]);
+            f.render_widget(Clear, popup_rect);
+            f.render_stateful_widget(list, popup_rect, &mut list_state);
+        }
     })?;
 
     execute!(stdout())?;
@@ -1,3 +1,5 @@
+use std::{io, path::PathBuf};
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -15,11 +17,38 @@ pub enum FilmanError {
     NoParentError,
 
     #[error("Failed to read directory")]
-    ReadDirectoryError(#[from] std::io::Error),
+    ReadDirectoryError(#[from] io::Error),
 
     #[error("This would overwrite an existing file: {0}")]
     FileOverwriteError(String),
 
+    #[error("File operation failed for {}", .0.join(", "))]
+    FileOperationError(Vec<String>),
+
+    #[error("Invalid mode {0}")]
+    InvalidMode(String),
+
+    #[error("No such user {0}")]
+    UnknownUser(String),
+
+    #[error("No such group {0}")]
+    UnknownGroup(String),
+
+    #[error("Failed to change permissions of {}: {source}", .path.display())]
+    ChmodError { path: PathBuf, source: io::Error },
+
+    #[error("Failed to change owner of {}: {source}", .path.display())]
+    ChownError { path: PathBuf, source: io::Error },
+
+    #[error("{}", .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    MultipleErrors(Vec<FilmanError>),
+
+    #[error("Nothing to undo")]
+    NothingToUndo,
+
+    #[error("Nothing to redo")]
+    NothingToRedo,
+
     #[error("Encountered non unicode characters in filename")]
     UnicodeError,
 
@@ -35,3 +64,15 @@ pub enum FilmanError {
     #[error("No selected file")]
     NoFileSelectedError,
 }
+
+impl FilmanError {
+    /// Ok if there are no errors, the error if there is one and
+    /// `MultipleErrors` otherwise
+    pub fn collect(mut errors: Vec<FilmanError>) -> Result<(), FilmanError> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(FilmanError::MultipleErrors(errors)),
+        }
+    }
+}
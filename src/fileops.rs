@@ -0,0 +1,395 @@
+use std::{
+    ffi::CString,
+    fmt,
+    fs::{self, File},
+    io::{self, Read, Write},
+    ops::AddAssign,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::error::FilmanError;
+
+/// The device of `path`, or of the closest ancestor that exists
+pub fn device_of(path: &Path) -> Option<u64> {
+    path.ancestors()
+        .find_map(|dir| fs::metadata(dir).ok())
+        .map(|metadata| metadata.dev())
+}
+
+/// Turns the failures collected by the recursive operations into an error
+pub fn check_failed(failed: Vec<(PathBuf, io::Error)>) -> Result<(), FilmanError> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+    Err(FilmanError::FileOperationError(
+        failed
+            .iter()
+            .map(|(path, e)| format!("{}: {}", path.display(), e))
+            .collect(),
+    ))
+}
+
+/// Copies `src` to `dst`, descending into directories. Symlinks are recreated
+/// rather than followed and permissions and modification times are kept.
+/// Entries that fail to copy are pushed to `failed` and the rest of the tree
+/// is still copied.
+pub fn copy_recursive(src: &Path, dst: &Path, failed: &mut Vec<(PathBuf, io::Error)>) {
+    copy_with_progress(src, dst, &Progress::new(), failed)
+}
+
+/// Like `copy_recursive` but reports copied files and bytes to `progress` and
+/// stops when it is cancelled
+pub fn copy_with_progress(
+    src: &Path,
+    dst: &Path,
+    progress: &Progress,
+    failed: &mut Vec<(PathBuf, io::Error)>,
+) {
+    if let Err(e) = copy_entry(src, dst, progress, failed) {
+        failed.push((src.to_path_buf(), e));
+    }
+}
+
+fn copy_entry(
+    src: &Path,
+    dst: &Path,
+    progress: &Progress,
+    failed: &mut Vec<(PathBuf, io::Error)>,
+) -> io::Result<()> {
+    progress.check_cancelled()?;
+    let metadata = fs::symlink_metadata(src)?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        let target = fs::read_link(src)?;
+        std::os::unix::fs::symlink(target, dst)?;
+        set_symlink_modified(dst, metadata.modified()?)?;
+        progress.files_done.fetch_add(1, Ordering::Relaxed);
+    } else if file_type.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            match entry {
+                Ok(entry) => copy_with_progress(
+                    &entry.path(),
+                    &dst.join(entry.file_name()),
+                    progress,
+                    failed,
+                ),
+                Err(e) => failed.push((src.to_path_buf(), e)),
+            }
+        }
+
+        // Set permissions last so that read-only directories can be filled
+        File::open(dst)?.set_modified(metadata.modified()?)?;
+        fs::set_permissions(dst, metadata.permissions())?;
+    } else {
+        let mut reader = File::open(src)?;
+        let mut writer = File::options().write(true).create_new(true).open(dst)?;
+        let copied = (|| {
+            let mut buffer = vec![0; 64 * 1024];
+            loop {
+                progress.check_cancelled()?;
+                let len = reader.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                writer.write_all(&buffer[..len])?;
+                progress.bytes_done.fetch_add(len as u64, Ordering::Relaxed);
+            }
+            writer.set_modified(metadata.modified()?)?;
+            writer.set_permissions(metadata.permissions())
+        })();
+
+        // Don't leave a partial copy behind
+        if let Err(e) = copied {
+            let _ = fs::remove_file(dst);
+            return Err(e);
+        }
+        progress.files_done.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Sets the modification time of a symlink itself rather than its target
+fn set_symlink_modified(path: &Path, modified: SystemTime) -> io::Result<()> {
+    let since_epoch = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_OMIT,
+        },
+        libc::timespec {
+            tv_sec: since_epoch.as_secs() as libc::time_t,
+            tv_nsec: since_epoch.subsec_nanos() as libc::c_long,
+        },
+    ];
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let result = unsafe {
+        libc::utimensat(libc::AT_FDCWD, path.as_ptr(), times.as_ptr(), libc::AT_SYMLINK_NOFOLLOW)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Moves `src` to `dst`. Falls back to copying and removing the source when
+/// the two are on different filesystems. The source is only removed if the
+/// whole tree was copied. Existing destinations are never replaced.
+pub fn move_recursive(src: &Path, dst: &Path, failed: &mut Vec<(PathBuf, io::Error)>) {
+    move_with_progress(src, dst, &Progress::new(), failed)
+}
+
+/// Like `move_recursive` but reports progress and stops when cancelled
+pub fn move_with_progress(
+    src: &Path,
+    dst: &Path,
+    progress: &Progress,
+    failed: &mut Vec<(PathBuf, io::Error)>,
+) {
+    // Unlike copying, renaming silently replaces existing files
+    if dst.symlink_metadata().is_ok() {
+        failed.push((
+            src.to_path_buf(),
+            io::Error::new(io::ErrorKind::AlreadyExists, "destination exists"),
+        ));
+        return;
+    }
+    if let Err(e) = progress.check_cancelled() {
+        failed.push((src.to_path_buf(), e));
+        return;
+    }
+
+    match fs::rename(src, dst) {
+        Ok(()) => {
+            if let Ok(summary) = tree_summary(dst) {
+                progress.add_done(summary);
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            move_by_copy(src, dst, progress, failed)
+        }
+        Err(e) => failed.push((src.to_path_buf(), e)),
+    }
+}
+
+/// Moves `src` to `dst` by copying it and removing the source, as is done
+/// across filesystems. If the copy fails or is cancelled the partial copy
+/// is removed and the source is left as it was.
+pub fn move_by_copy(
+    src: &Path,
+    dst: &Path,
+    progress: &Progress,
+    failed: &mut Vec<(PathBuf, io::Error)>,
+) {
+    let mut copy_failed = vec![];
+    copy_with_progress(src, dst, progress, &mut copy_failed);
+    if !copy_failed.is_empty() {
+        failed.append(&mut copy_failed);
+        if dst.symlink_metadata().is_ok() {
+            if let Err(e) = remove_recursive(dst) {
+                failed.push((dst.to_path_buf(), e));
+            }
+        }
+    } else if let Err(e) = remove_recursive(src) {
+        failed.push((src.to_path_buf(), e));
+    }
+}
+
+/// Progress of a long running file operation, shared between the worker
+/// thread doing the operation and the UI
+#[derive(Debug)]
+pub struct Progress {
+    started: Instant,
+    cancelled: AtomicBool,
+    pub files_total: AtomicU64,
+    pub files_done: AtomicU64,
+    pub bytes_total: AtomicU64,
+    pub bytes_done: AtomicU64,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Progress {
+            started: Instant::now(),
+            cancelled: AtomicBool::new(false),
+            files_total: AtomicU64::new(0),
+            files_done: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+            bytes_done: AtomicU64::new(0),
+        }
+    }
+
+    pub fn add_total(&self, summary: TreeSummary) {
+        self.files_total.fetch_add(summary.files, Ordering::Relaxed);
+        self.bytes_total.fetch_add(summary.bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_done(&self, summary: TreeSummary) {
+        self.files_done.fetch_add(summary.files, Ordering::Relaxed);
+        self.bytes_done.fetch_add(summary.bytes, Ordering::Relaxed);
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    fn check_cancelled(&self) -> io::Result<()> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+        }
+        Ok(())
+    }
+
+    /// Estimated time left based on the rate bytes have been processed so far
+    pub fn eta(&self) -> Option<Duration> {
+        let done = self.bytes_done.load(Ordering::Relaxed);
+        let total = self.bytes_total.load(Ordering::Relaxed);
+        if done == 0 || done > total {
+            return None;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        Some(Duration::from_secs_f64(
+            elapsed * (total - done) as f64 / done as f64,
+        ))
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}, {}/{} files",
+            human_bytes::human_bytes(self.bytes_done.load(Ordering::Relaxed) as f64),
+            human_bytes::human_bytes(self.bytes_total.load(Ordering::Relaxed) as f64),
+            thousands(self.files_done.load(Ordering::Relaxed)),
+            thousands(self.files_total.load(Ordering::Relaxed)),
+        )?;
+        if let Some(eta) = self.eta() {
+            write!(f, ", ETA {}s", eta.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes a file, symlink or a whole directory tree.
+pub fn remove_recursive(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// The path to `target` relative to the directory `dir`. Both paths should
+/// be absolute.
+pub fn relative_path(dir: &Path, target: &Path) -> PathBuf {
+    let dir = dir.components().collect::<Vec<_>>();
+    let target = target.components().collect::<Vec<_>>();
+    let common = dir
+        .iter()
+        .zip(&target)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut ret = PathBuf::new();
+    for _ in common..dir.len() {
+        ret.push("..");
+    }
+    ret.extend(&target[common..]);
+    ret
+}
+
+/// Number of directories, files and bytes in a directory tree
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TreeSummary {
+    pub dirs: u64,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl AddAssign for TreeSummary {
+    fn add_assign(&mut self, other: Self) {
+        self.dirs += other.dirs;
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+}
+
+impl fmt::Display for TreeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} dirs, {} files, {}",
+            thousands(self.dirs),
+            thousands(self.files),
+            human_bytes::human_bytes(self.bytes as f64)
+        )
+    }
+}
+
+/// Counts everything in the tree at `path` without following symlinks
+pub fn tree_summary(path: &Path) -> io::Result<TreeSummary> {
+    let metadata = fs::symlink_metadata(path)?;
+    let mut summary = TreeSummary::default();
+    if metadata.is_dir() {
+        summary.dirs += 1;
+        for entry in fs::read_dir(path)? {
+            summary += tree_summary(&entry?.path())?;
+        }
+    } else {
+        summary.files += 1;
+        summary.bytes += metadata.len();
+    }
+    Ok(summary)
+}
+
+/// Like `tree_summary` but stops counting after `limit` entries. Returns
+/// false along with the partial count if it stopped early.
+pub fn tree_summary_up_to(path: &Path, limit: u64) -> io::Result<(TreeSummary, bool)> {
+    fn count(path: &Path, limit: u64, summary: &mut TreeSummary) -> io::Result<bool> {
+        if summary.dirs + summary.files >= limit {
+            return Ok(false);
+        }
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.is_dir() {
+            summary.dirs += 1;
+            for entry in fs::read_dir(path)? {
+                if !count(&entry?.path(), limit, summary)? {
+                    return Ok(false);
+                }
+            }
+        } else {
+            summary.files += 1;
+            summary.bytes += metadata.len();
+        }
+        Ok(true)
+    }
+
+    let mut summary = TreeSummary::default();
+    let complete = count(path, limit, &mut summary)?;
+    Ok((summary, complete))
+}
+
+fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let mut ret = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            ret.push(',');
+        }
+        ret.push(c);
+    }
+    ret
+}
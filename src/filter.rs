@@ -0,0 +1,73 @@
+use std::fmt;
+
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexBuilder};
+
+use crate::error::FilmanError;
+
+/// Narrows a directory listing by name. Filters starting with `re:` are
+/// regexes, filters with `*`, `?` or `[` are globs and anything else matches
+/// as a substring. Matching ignores case unless the filter has uppercase
+/// letters.
+#[derive(Clone, Debug)]
+pub enum Filter {
+    Substring { text: String, ignore_case: bool },
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl Filter {
+    pub fn parse(filter: &str) -> Result<Self, FilmanError> {
+        let ignore_case = !filter.chars().any(char::is_uppercase);
+
+        if let Some(pattern) = filter.strip_prefix("re:") {
+            return RegexBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .build()
+                .map(Filter::Regex)
+                .map_err(|e| FilmanError::CommandParseError(e.to_string()));
+        }
+
+        if filter.contains(['*', '?', '[']) {
+            return GlobBuilder::new(filter)
+                .case_insensitive(ignore_case)
+                .literal_separator(true)
+                .build()
+                .map(|glob| Filter::Glob(glob.compile_matcher()))
+                .map_err(|e| FilmanError::CommandParseError(e.to_string()));
+        }
+
+        Ok(Filter::Substring {
+            text: if ignore_case {
+                filter.to_lowercase()
+            } else {
+                filter.to_string()
+            },
+            ignore_case,
+        })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            Filter::Substring {
+                text,
+                ignore_case: true,
+            } => name.to_lowercase().contains(text),
+            Filter::Substring { text, .. } => name.contains(text),
+            Filter::Glob(glob) => glob.is_match(name),
+            Filter::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// The filter as it was typed
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Only filters without uppercase letters are lowercased
+            Filter::Substring { text, .. } => write!(f, "{}", text),
+            Filter::Glob(glob) => write!(f, "{}", glob.glob()),
+            Filter::Regex(regex) => write!(f, "re:{}", regex.as_str()),
+        }
+    }
+}
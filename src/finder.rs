@@ -0,0 +1,187 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+};
+
+use prompter::PromptReader;
+
+use crate::hidden::walk;
+
+/// How many ranked results are kept
+const MAX_RESULTS: usize = 500;
+
+/// A path that matched the query, with the character indices that matched
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FindResult {
+    pub path: PathBuf,
+    pub score: i64,
+    pub matched: Vec<usize>,
+}
+
+/// The state of the `:find` popup. The tree below `root` is walked on a
+/// background thread that stops when the finder is dropped.
+#[derive(Clone, Debug)]
+pub struct Finder {
+    pub reader: PromptReader,
+    pub root: PathBuf,
+    /// Paths relative to `root`, filled in by the walker
+    paths: Arc<Mutex<Vec<PathBuf>>>,
+    walking: Arc<AtomicBool>,
+    results: Vec<FindResult>,
+    /// Index into `results` of the highlighted result
+    pub cursor: usize,
+    /// The query and number of walked paths `results` were ranked for
+    ranked_for: Option<(String, usize)>,
+}
+
+impl Finder {
+    pub fn new(root: PathBuf, show_hidden: bool, respect_ignore: bool) -> Self {
+        let paths = Arc::new(Mutex::new(vec![]));
+        let walking = Arc::new(AtomicBool::new(true));
+        let walker = walk(&root, show_hidden, respect_ignore);
+
+        let shared: Weak<Mutex<Vec<PathBuf>>> = Arc::downgrade(&paths);
+        let done = walking.clone();
+        let walk_root = root.clone();
+        thread::spawn(move || {
+            for entry in walker.flatten() {
+                // Nobody is interested any more
+                let Some(paths) = shared.upgrade() else {
+                    break;
+                };
+                if let Ok(relative) = entry.path().strip_prefix(&walk_root) {
+                    if !relative.as_os_str().is_empty() {
+                        paths.lock().unwrap().push(relative.to_path_buf());
+                    }
+                }
+            }
+            // Pairs with the Acquire in is_walking so that every path pushed
+            // above is seen once the walk is reported as finished
+            done.store(false, Ordering::Release);
+        });
+
+        Finder {
+            reader: PromptReader::new_with_placeholder("", None),
+            root,
+            paths,
+            walking,
+            results: vec![],
+            cursor: 0,
+            ranked_for: None,
+        }
+    }
+
+    pub fn is_walking(&self) -> bool {
+        self.walking.load(Ordering::Acquire)
+    }
+
+    /// Number of paths walked so far
+    pub fn walked(&self) -> usize {
+        self.paths.lock().unwrap().len()
+    }
+
+    /// Ranks the walked paths again if the query changed. Otherwise only the
+    /// paths found since the last time are ranked and merged in.
+    pub fn refresh(&mut self) {
+        let query = self.reader.result().to_string();
+        let (ranked, new_query) = match &self.ranked_for {
+            Some((q, ranked)) if *q == query => (*ranked, false),
+            _ => (0, true),
+        };
+
+        // The walker is not held up while ranking
+        let paths = {
+            let paths = self.paths.lock().unwrap();
+            if !new_query && paths.len() == ranked {
+                return;
+            }
+            paths[ranked..].to_vec()
+        };
+
+        let mut results = match new_query {
+            true => vec![],
+            false => std::mem::take(&mut self.results),
+        };
+        results.extend(paths.iter().filter_map(|path| {
+            let (score, matched) = fuzzy_match(&query, &path.to_string_lossy())?;
+            Some(FindResult {
+                path: path.clone(),
+                score,
+                matched,
+            })
+        }));
+        results.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+        results.truncate(MAX_RESULTS);
+
+        // A new query starts from the best result
+        if new_query {
+            self.cursor = 0;
+        }
+        self.ranked_for = Some((query, ranked + paths.len()));
+        self.results = results;
+        self.cursor = self.cursor.min(self.results.len().saturating_sub(1));
+    }
+
+    pub fn results(&self) -> &[FindResult] {
+        &self.results
+    }
+
+    pub fn cursor_down(&mut self) {
+        if self.cursor + 1 < self.results.len() {
+            self.cursor += 1;
+        }
+    }
+
+    pub fn cursor_up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    /// The full path of the highlighted result
+    pub fn selected(&self) -> Option<PathBuf> {
+        self.results
+            .get(self.cursor)
+            .map(|result| self.root.join(&result.path))
+    }
+}
+
+/// Matches `query` as a subsequence of `candidate`, ignoring case unless the
+/// query has uppercase letters. Consecutive characters, characters at the
+/// start of a word and matches in the file name score higher, long paths
+/// score lower. Returns the score and the matched character indices.
+pub fn fuzzy_match(query: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let ignore_case = !query.chars().any(char::is_uppercase);
+    let fold = |c: char| match ignore_case {
+        true => c.to_lowercase().next().unwrap_or(c),
+        false => c,
+    };
+
+    let chars = candidate.chars().collect::<Vec<_>>();
+    let name_start = candidate
+        .rfind('/')
+        .map_or(0, |i| candidate[..=i].chars().count());
+    let mut matched = vec![];
+    let mut score = 0;
+    let mut next = 0;
+    for q in query.chars().map(fold) {
+        let index = (next..chars.len()).find(|&i| fold(chars[i]) == q)?;
+        score += 1;
+        if matched.last().is_some_and(|&last| last + 1 == index) {
+            score += 5;
+        }
+        if index == 0 || matches!(chars[index - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 3;
+        }
+        if index >= name_start {
+            score += 2;
+        }
+        matched.push(index);
+        next = index + 1;
+    }
+
+    score -= chars.len() as i64 / 8;
+    Some((score, matched))
+}
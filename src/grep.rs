@@ -0,0 +1,170 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{error::FilmanError, hidden::walk_contents};
+
+/// Searching stops after this many matching lines
+const MAX_MATCHES: usize = 10_000;
+
+/// Files with a NUL byte this close to the start are taken to be binary
+const BINARY_CHECK_LENGTH: usize = 8192;
+
+/// Larger files are skipped
+const MAX_FILE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Snippets are cut after this many characters
+const SNIPPET_LENGTH: usize = 200;
+
+/// A line that matched, `line` counts from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrepMatch {
+    pub path: PathBuf,
+    pub line: usize,
+    pub snippet: String,
+}
+
+/// The matches of a `:grep`, shown in place of the files. The tree below
+/// `root` is searched on a background thread that stops when the results
+/// are dropped.
+#[derive(Clone, Debug)]
+pub struct GrepResults {
+    pub pattern: String,
+    /// Paths of the matches are relative to `root`
+    pub root: PathBuf,
+    /// The matches found so far, see `refresh`
+    pub matches: Vec<GrepMatch>,
+    pub cursor: usize,
+    /// Filled in by the search thread
+    found: Arc<Mutex<Vec<GrepMatch>>>,
+    searching: Arc<AtomicBool>,
+}
+
+impl GrepResults {
+    pub fn new(pattern: String, regex: Regex, root: PathBuf, show_hidden: bool) -> Self {
+        let found = Arc::new(Mutex::new(vec![]));
+        let searching = Arc::new(AtomicBool::new(true));
+        let walker = walk_contents(&root, show_hidden);
+
+        let shared: Weak<Mutex<Vec<GrepMatch>>> = Arc::downgrade(&found);
+        let done = searching.clone();
+        let search_root = root.clone();
+        thread::spawn(move || {
+            'walk: for entry in walker.flatten() {
+                // Nobody is interested any more
+                let Some(found) = shared.upgrade() else {
+                    break;
+                };
+                if !entry.file_type().is_some_and(|t| t.is_file())
+                    || entry.metadata().map_or(true, |m| m.len() > MAX_FILE_SIZE)
+                {
+                    continue;
+                }
+                let Ok(lines) = grep_file(entry.path(), &regex) else {
+                    continue;
+                };
+
+                let path = entry
+                    .path()
+                    .strip_prefix(&search_root)
+                    .unwrap_or(entry.path());
+                let mut found = found.lock().unwrap();
+                for (line, snippet) in lines {
+                    if found.len() == MAX_MATCHES {
+                        break 'walk;
+                    }
+                    found.push(GrepMatch {
+                        path: path.to_path_buf(),
+                        line,
+                        snippet,
+                    });
+                }
+            }
+            // Pairs with the Acquire in is_searching
+            done.store(false, Ordering::Release);
+        });
+
+        GrepResults {
+            pattern,
+            root,
+            matches: vec![],
+            cursor: 0,
+            found,
+            searching,
+        }
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.searching.load(Ordering::Acquire)
+    }
+
+    /// Takes in the matches found since the last time
+    pub fn refresh(&mut self) {
+        let found = self.found.lock().unwrap();
+        if found.len() > self.matches.len() {
+            self.matches.extend_from_slice(&found[self.matches.len()..]);
+        }
+    }
+
+    pub fn cursor_down(&mut self) {
+        if self.cursor + 1 < self.matches.len() {
+            self.cursor += 1;
+        }
+    }
+
+    pub fn cursor_up(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    /// The full path and line of the highlighted match
+    pub fn selected(&self) -> Option<(PathBuf, usize)> {
+        self.matches
+            .get(self.cursor)
+            .map(|m| (self.root.join(&m.path), m.line))
+    }
+}
+
+/// Regex for a grep pattern, ignoring case unless it has uppercase letters
+pub fn parse_pattern(pattern: &str) -> Result<Regex, FilmanError> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(!pattern.chars().any(char::is_uppercase))
+        .build()
+        .map_err(|e| FilmanError::CommandParseError(e.to_string()))
+}
+
+/// The numbers and snippets of the lines of `path` that match, reading one
+/// line at a time. Binary files have no matches.
+fn grep_file(path: &Path, regex: &Regex) -> io::Result<Vec<(usize, String)>> {
+    let mut reader = BufReader::with_capacity(BINARY_CHECK_LENGTH, File::open(path)?);
+    if reader.fill_buf()?.contains(&0) {
+        return Ok(vec![]);
+    }
+
+    let mut matches = vec![];
+    let mut bytes = vec![];
+    let mut number = 0;
+    loop {
+        bytes.clear();
+        if reader.read_until(b'\n', &mut bytes)? == 0 {
+            return Ok(matches);
+        }
+        number += 1;
+        let line = String::from_utf8_lossy(&bytes);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if regex.is_match(line) {
+            matches.push((number, line.trim().chars().take(SNIPPET_LENGTH).collect()));
+            if matches.len() == MAX_MATCHES {
+                return Ok(matches);
+            }
+        }
+    }
+}
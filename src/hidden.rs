@@ -0,0 +1,135 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::SystemTime,
+};
+
+use ignore::{gitignore::Gitignore, Match, Walk, WalkBuilder};
+
+/// Dotfiles are hidden unless `show_hidden` is set
+pub fn is_dotfile(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+/// The `.gitignore` and `.ignore` rules that apply in a directory, from the
+/// directory itself and every directory above it up to the repository root
+#[derive(Debug)]
+pub struct IgnoreRules {
+    /// Deepest directory first, `.ignore` before `.gitignore`
+    matchers: Vec<Gitignore>,
+    /// Every ignore file that was looked for, with its mtime if it exists
+    sources: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+impl IgnoreRules {
+    pub fn for_dir(dir: &Path) -> Self {
+        let mut matchers = vec![];
+        let mut sources = vec![];
+        for ancestor in dir.ancestors() {
+            for name in [".ignore", ".gitignore"] {
+                let file = ancestor.join(name);
+                if file.is_file() {
+                    let (matcher, _) = Gitignore::new(&file);
+                    matchers.push(matcher);
+                }
+                sources.push((file.clone(), modified(&file)));
+            }
+            // Files above the repository don't apply to it
+            if ancestor.join(".git").exists() {
+                break;
+            }
+        }
+        IgnoreRules { matchers, sources }
+    }
+
+    /// Whether an ignore file was changed, created or removed since the
+    /// rules were read
+    pub fn is_stale(&self) -> bool {
+        self.sources
+            .iter()
+            .any(|(file, time)| modified(file) != *time)
+    }
+
+    /// The rules of the deepest file that mentions `path` decide. A
+    /// whitelisted `!pattern` overrides the files further up.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let is_dir = path.is_dir();
+        for matcher in &self.matchers {
+            match matcher.matched_path_or_any_parents(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+/// Parsed `IgnoreRules` of the listed directories. The rules are read again
+/// after pwd changes, `clear` is called or one of their files changes.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreCache {
+    /// The pwd the rules were read for and the rules of each directory
+    rules: RefCell<(PathBuf, HashMap<PathBuf, Rc<IgnoreRules>>)>,
+}
+
+impl IgnoreCache {
+    pub fn rules(&self, pwd: &Path, dir: &Path) -> Rc<IgnoreRules> {
+        let mut cache = self.rules.borrow_mut();
+        let (cached_pwd, rules) = &mut *cache;
+        if cached_pwd != pwd {
+            *cached_pwd = pwd.to_path_buf();
+            rules.clear();
+        }
+        match rules.get(dir) {
+            Some(cached) if !cached.is_stale() => cached.clone(),
+            _ => {
+                let fresh = Rc::new(IgnoreRules::for_dir(dir));
+                rules.insert(dir.to_path_buf(), fresh.clone());
+                fresh
+            }
+        }
+    }
+
+    pub fn clear(&self) {
+        self.rules.borrow_mut().1.clear();
+    }
+}
+
+/// The mtime of a regular file, `None` if there is no such file
+fn modified(file: &Path) -> Option<SystemTime> {
+    file.metadata()
+        .ok()
+        .filter(|m| m.is_file())
+        .and_then(|m| m.modified().ok())
+}
+
+/// Walks the tree below `root` in name order, leaving out what the listings
+/// would hide
+pub fn walk(root: &Path, show_hidden: bool, respect_ignore: bool) -> Walk {
+    WalkBuilder::new(root)
+        .hidden(!show_hidden)
+        .ignore(respect_ignore)
+        .git_ignore(respect_ignore)
+        .git_exclude(respect_ignore)
+        .git_global(false)
+        .parents(respect_ignore)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+}
+
+/// Walks the tree below `root` for a content search. Ignore files always
+/// apply and `.git` is never entered, whatever the listings show.
+pub fn walk_contents(root: &Path, show_hidden: bool) -> Walk {
+    WalkBuilder::new(root)
+        .hidden(!show_hidden)
+        .git_global(false)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build()
+}
@@ -1,8 +1,13 @@
 use std::collections::HashMap;
 
-use crate::config::Config;
+use crate::config::CONFIG;
+use crate::error::FilmanError;
+use crate::finder::Finder;
+use crate::grep::GrepResults;
 use crate::path::Path;
-use crate::state::{Mode, State};
+use crate::search::SearchHistory;
+use crate::state::{Mode, OutputPane, State};
+use crate::tokenize::quote;
 use crossterm::event::{KeyCode, KeyEvent};
 use lazy_static::lazy_static;
 use prompter::PromptReader;
@@ -23,13 +28,12 @@ lazy_static! {
                 PromptReader::new_with_placeholder("!", None),
             ))],
         );
-        let config = Config::new("config.json".into());
-        let keys = config.simple_keymap_actions();
+        let keys = CONFIG.simple_keymap_actions();
         keymap.extend(keys.into_iter());
         keymap
     };
 }
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Action {
     ShellCommand(String),
     Command(String),
@@ -50,10 +54,11 @@ pub fn shell_mode_input(key: &KeyEvent, reader: &mut PromptReader) -> Vec<Action
         _ => {}
     }
 
+    // Leave shell command mode first so that the output can be shown
     if reader.done() {
         vec![
-            Action::ShellCommand(reader.result().to_string()),
             Action::ModeSwitch(Mode::NormalMode),
+            Action::ShellCommand(reader.result().to_string()),
         ]
     } else {
         vec![]
@@ -72,30 +77,220 @@ pub fn command_mode_input(key: &KeyEvent, reader: &mut PromptReader) -> Vec<Acti
         _ => {}
     }
 
+    // Leave command mode before running the command so that the command
+    // itself can switch mode
     if reader.done() {
         vec![
-            Action::Command(reader.result().to_string()),
             Action::ModeSwitch(Mode::NormalMode),
+            Action::Command(reader.result().to_string()),
         ]
     } else {
         vec![]
     }
 }
 
+/// Like `command_mode_input` but the filter is applied after every key.
+/// Enter keeps the filter and Esc clears it.
+pub fn filter_mode_input(key: &KeyEvent, reader: &mut PromptReader) -> Vec<Action> {
+    match key.code {
+        KeyCode::Char(c) => reader.next_key(c.into()),
+        KeyCode::Enter => reader.next_key(prompter::keycodes::KeyCode::Enter),
+        KeyCode::Left => reader.next_key(prompter::keycodes::KeyCode::Left),
+        KeyCode::Right => reader.next_key(prompter::keycodes::KeyCode::Right),
+        KeyCode::Backspace => reader.next_key(prompter::keycodes::KeyCode::Backspace),
+        KeyCode::Delete => reader.next_key(prompter::keycodes::KeyCode::Delete),
+        KeyCode::Esc => {
+            return vec![
+                Action::ModeSwitch(Mode::NormalMode),
+                Action::Command(":filter".into()),
+            ]
+        }
+        _ => {}
+    }
+
+    let mut ret = vec![];
+    if reader.done() {
+        ret.push(Action::ModeSwitch(Mode::NormalMode));
+    }
+    ret.push(match reader.result() {
+        "" => Action::Command(":filter".into()),
+        filter => Action::Command(format!(":filter {}", quote(filter))),
+    });
+    ret
+}
+
+/// Searches after every key so that the cursor follows the typing. Up and
+/// Down browse earlier searches and Enter adds the search to the history.
+/// Esc or an empty search puts back the search and cursor from before.
+pub fn search_mode_input(
+    key: &KeyEvent,
+    reader: &mut PromptReader,
+    history_index: &mut Option<usize>,
+    history: &SearchHistory,
+) -> Vec<Action> {
+    match key.code {
+        KeyCode::Up | KeyCode::Down if !history.is_empty() => {
+            *history_index = match (key.code, *history_index) {
+                (KeyCode::Up, None) => Some(0),
+                (KeyCode::Up, Some(i)) => Some((i + 1).min(history.len() - 1)),
+                (_, None | Some(0)) => None,
+                (_, Some(i)) => Some(i - 1),
+            };
+            let search = history_index.and_then(|i| history.get(i)).unwrap_or("");
+            *reader = PromptReader::new_with_placeholder(search, None);
+        }
+        KeyCode::Char(c) => reader.next_key(c.into()),
+        KeyCode::Enter => reader.next_key(prompter::keycodes::KeyCode::Enter),
+        KeyCode::Left => reader.next_key(prompter::keycodes::KeyCode::Left),
+        KeyCode::Right => reader.next_key(prompter::keycodes::KeyCode::Right),
+        KeyCode::Backspace => reader.next_key(prompter::keycodes::KeyCode::Backspace),
+        KeyCode::Delete => reader.next_key(prompter::keycodes::KeyCode::Delete),
+        KeyCode::Esc => {
+            return vec![
+                Action::Command(":search_abort".into()),
+                Action::ModeSwitch(Mode::NormalMode),
+            ]
+        }
+        _ => {}
+    }
+
+    match (reader.done(), reader.result()) {
+        (true, "") => vec![
+            Action::Command(":search_abort".into()),
+            Action::ModeSwitch(Mode::NormalMode),
+        ],
+        (true, search) => vec![
+            Action::ModeSwitch(Mode::NormalMode),
+            Action::Command(format!(":search -- {}", quote(search))),
+        ],
+        (false, "") => vec![Action::Command(":search_abort".into())],
+        (false, search) => vec![Action::Command(format!(":search -i -- {}", quote(search)))],
+    }
+}
+
+/// Up and Down move through the results of the finder, Tab adds the
+/// highlighted one to the selection and Enter jumps to it
+pub fn find_mode_input(key: &KeyEvent, finder: &mut Finder) -> Vec<Action> {
+    match key.code {
+        KeyCode::Up => finder.cursor_up(),
+        KeyCode::Down => finder.cursor_down(),
+        KeyCode::Tab => {
+            if let Some(path) = finder.selected() {
+                return vec![Action::Command(format!(
+                    ":toggle_select {}",
+                    quote(&path.to_string_lossy())
+                ))];
+            }
+        }
+        KeyCode::Enter => {
+            let mut ret = vec![Action::ModeSwitch(Mode::NormalMode)];
+            if let Some(path) = finder.selected() {
+                ret.push(Action::Command(format!(
+                    ":goto {}",
+                    quote(&path.to_string_lossy())
+                )));
+            }
+            return ret;
+        }
+        KeyCode::Char(c) => finder.reader.next_key(c.into()),
+        KeyCode::Left => finder.reader.next_key(prompter::keycodes::KeyCode::Left),
+        KeyCode::Right => finder.reader.next_key(prompter::keycodes::KeyCode::Right),
+        KeyCode::Backspace => finder.reader.next_key(prompter::keycodes::KeyCode::Backspace),
+        KeyCode::Delete => finder.reader.next_key(prompter::keycodes::KeyCode::Delete),
+        KeyCode::Esc => return vec![Action::ModeSwitch(Mode::NormalMode)],
+        _ => {}
+    }
+    finder.refresh();
+    vec![]
+}
+
+/// j and k move through the matches and Enter jumps to the highlighted one
+pub fn grep_mode_input(key: &KeyEvent, results: &mut GrepResults) -> Vec<Action> {
+    match key.code {
+        KeyCode::Char('j') | KeyCode::Down => results.cursor_down(),
+        KeyCode::Char('k') | KeyCode::Up => results.cursor_up(),
+        KeyCode::Enter => {
+            let mut ret = vec![Action::ModeSwitch(Mode::NormalMode)];
+            if let Some((path, line)) = results.selected() {
+                ret.push(Action::Command(format!(
+                    ":goto {} {}",
+                    quote(&path.to_string_lossy()),
+                    line
+                )));
+            }
+            return ret;
+        }
+        KeyCode::Char('q') | KeyCode::Esc => return vec![Action::ModeSwitch(Mode::NormalMode)],
+        _ => {}
+    }
+    vec![]
+}
+
+pub fn output_mode_input(key: &KeyEvent, pane: &mut OutputPane) -> Vec<Action> {
+    match key.code {
+        KeyCode::Char('j') | KeyCode::Down => pane.scroll_down(),
+        KeyCode::Char('k') | KeyCode::Up => pane.scroll_up(),
+        KeyCode::Char('q') | KeyCode::Esc | KeyCode::Enter => {
+            return vec![Action::ModeSwitch(Mode::NormalMode)]
+        }
+        _ => {}
+    }
+    vec![]
+}
+
+pub fn conflict_mode_input(key: &KeyEvent) -> Vec<Action> {
+    let resolution = match key.code {
+        KeyCode::Char('o') => "overwrite",
+        KeyCode::Char('O') => "overwrite --all",
+        KeyCode::Char('s') => "skip",
+        KeyCode::Char('S') => "skip --all",
+        KeyCode::Char('r') => "rename",
+        KeyCode::Char('R') => "rename --all",
+        KeyCode::Esc | KeyCode::Char('q') => return vec![Action::ModeSwitch(Mode::NormalMode)],
+        _ => return vec![],
+    };
+    vec![Action::Command(format!(":resolve_conflict {}", resolution))]
+}
+
+pub fn confirm_mode_input(key: &KeyEvent, actions: &[Action]) -> Vec<Action> {
+    let mut ret = vec![Action::ModeSwitch(Mode::NormalMode)];
+    if let KeyCode::Char('y') | KeyCode::Char('Y') = key.code {
+        ret.extend(actions.iter().cloned());
+    }
+    ret
+}
+
+pub fn rename_preview_mode_input(key: &KeyEvent) -> Vec<Action> {
+    match key.code {
+        KeyCode::Char('y') | KeyCode::Char('Y') => {
+            vec![Action::Command(":confirm_renames".into())]
+        }
+        _ => vec![Action::ModeSwitch(Mode::NormalMode)],
+    }
+}
+
 pub fn normal_mode_input(key: &KeyEvent, state: &State) -> Vec<Action> {
     match key.code {
-        KeyCode::Char('q') => return vec![Action::Quit],
+        KeyCode::Char('q') => vec![Action::Quit],
         KeyCode::Char('D') => {
-            let args = state.multi_select_or_selected().map(|x| {
-                x.iter()
-                    .map(|x| x.full_path_str().unwrap())
-                    .collect::<Vec<&str>>()
-                    .join(" ")
-            });
+            let args = quoted_selection(state);
 
             if let Ok(args) = args {
                 vec![Action::ModeSwitch(Mode::CommandMode(
-                    PromptReader::new_with_placeholder(&format!(":delete {}", args), None),
+                    PromptReader::new_with_placeholder(&format!(":delete -- {}", args), None),
+                ))]
+            } else {
+                vec![Action::SetErrorMessage(
+                    "Failed to read filename of selected".into(),
+                )]
+            }
+        }
+        KeyCode::Char('T') => {
+            let args = quoted_selection(state);
+
+            if let Ok(args) = args {
+                vec![Action::ModeSwitch(Mode::CommandMode(
+                    PromptReader::new_with_placeholder(&format!(":trash {}", args), None),
                 ))]
             } else {
                 vec![Action::SetErrorMessage(
@@ -106,7 +301,7 @@ pub fn normal_mode_input(key: &KeyEvent, state: &State) -> Vec<Action> {
         KeyCode::Char(' ') => {
             if let Ok(filename) = state.filename_of_selected() {
                 vec![
-                    Action::Command(format!(":toggle_select {}", filename)),
+                    Action::Command(format!(":toggle_select {}", quote(&filename))),
                     Action::Command(":cursor_down".into()),
                 ]
             } else {
@@ -114,12 +309,7 @@ pub fn normal_mode_input(key: &KeyEvent, state: &State) -> Vec<Action> {
             }
         }
         KeyCode::Char('y') => {
-            let args = state.multi_select_or_selected().map(|x| {
-                x.iter()
-                    .map(|x| x.full_path_str().unwrap())
-                    .collect::<Vec<&str>>()
-                    .join(" ")
-            });
+            let args = quoted_selection(state);
 
             if let Ok(args) = args {
                 vec![
@@ -132,20 +322,73 @@ pub fn normal_mode_input(key: &KeyEvent, state: &State) -> Vec<Action> {
                 )]
             }
         }
+        KeyCode::Char('x') => {
+            let args = quoted_selection(state);
+
+            if let Ok(args) = args {
+                vec![
+                    Action::Command(format!(":cut {}", args)),
+                    Action::Command(":clear_selection".into()),
+                ]
+            } else {
+                vec![Action::SetErrorMessage(
+                    "Failed to read filename of selected".into(),
+                )]
+            }
+        }
         KeyCode::Char('A') => {
             if let Ok(filename) = state.filename_of_selected() {
+                let filename = quote(&filename);
                 vec![Action::ModeSwitch(Mode::CommandMode(
-                    PromptReader::new_with_placeholder(&format!(":rename {}", filename), None),
+                    PromptReader::new_with_placeholder(
+                        &format!(":rename -- {} {}", filename, filename),
+                        None,
+                    ),
                 ))]
             } else {
                 vec![Action::SetErrorMessage("Failed to read filename".into())]
             }
         }
+        KeyCode::Char('M') => vec![Action::ModeSwitch(Mode::CommandMode(
+            PromptReader::new_with_placeholder(":mkdir ", None),
+        ))],
+        KeyCode::Char('F') => vec![Action::ModeSwitch(Mode::CommandMode(
+            PromptReader::new_with_placeholder(":touch ", None),
+        ))],
+        KeyCode::Char('/') => vec![Action::ModeSwitch(Mode::SearchMode {
+            reader: PromptReader::new_with_placeholder("", None),
+            history_index: None,
+            previous_search: state.search.clone(),
+            previous_cursor: state.selected_index_in_pwd(),
+        })],
+        KeyCode::Char('n') => vec![Action::Command(":search_next".into())],
+        KeyCode::Char('N') => vec![Action::Command(":search_prev".into())],
+        KeyCode::Char('f') => {
+            let filter = state
+                .filters
+                .get(&state.pwd)
+                .map(|filter| filter.to_string())
+                .unwrap_or_default();
+            vec![Action::ModeSwitch(Mode::FilterMode(
+                PromptReader::new_with_placeholder(&filter, None),
+            ))]
+        }
+        KeyCode::Enter => vec![Action::Command(":cursor_descend".into())],
         KeyCode::Char(c) => custom_simple_binding(c),
         _ => vec![],
     }
 }
 
+/// The full paths of the selection, quoted and joined into command arguments
+fn quoted_selection(state: &State) -> Result<String, FilmanError> {
+    Ok(state
+        .multi_select_or_selected()?
+        .iter()
+        .map(|x| x.full_path_str().map(quote))
+        .collect::<Result<Vec<_>, _>>()?
+        .join(" "))
+}
+
 fn custom_simple_binding(c: char) -> Vec<Action> {
     KEYBINDINGS.get(&c).cloned().unwrap_or(vec![])
 }
@@ -0,0 +1,111 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use crate::{error::FilmanError, fileops::Progress, journal::Operation};
+
+/// What a finished job did. The operation is journaled even if the job
+/// failed part way so that it can be undone.
+#[derive(Debug)]
+pub struct JobOutcome {
+    pub operation: Option<Operation>,
+    pub result: Result<(), FilmanError>,
+}
+
+/// A file operation running on a background thread
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub id: usize,
+    pub description: String,
+    pub progress: Arc<Progress>,
+    handle: Arc<Mutex<Option<JoinHandle<JobOutcome>>>>,
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        match self.handle.lock().unwrap().as_ref() {
+            Some(handle) => handle.is_finished(),
+            None => true,
+        }
+    }
+
+    fn join(&self) -> Option<JobOutcome> {
+        let handle = self.handle.lock().unwrap().take()?;
+        Some(handle.join().unwrap_or_else(|_| JobOutcome {
+            operation: None,
+            result: Err(FilmanError::CommandError(format!(
+                "Job {} panicked",
+                self.id
+            ))),
+        }))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Jobs {
+    next_id: usize,
+    running: Vec<Job>,
+}
+
+impl Jobs {
+    /// Runs `work` on a new thread and returns the id of the job
+    pub fn spawn<F>(&mut self, description: String, work: F) -> usize
+    where
+        F: FnOnce(&Progress) -> JobOutcome + Send + 'static,
+    {
+        self.next_id += 1;
+        let progress = Arc::new(Progress::new());
+        let worker_progress = progress.clone();
+        let handle = thread::spawn(move || work(&worker_progress));
+        self.running.push(Job {
+            id: self.next_id,
+            description,
+            progress,
+            handle: Arc::new(Mutex::new(Some(handle))),
+        });
+        self.next_id
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Job> {
+        self.running.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    pub fn cancel(&self, id: usize) -> Result<(), FilmanError> {
+        let job = self
+            .running
+            .iter()
+            .find(|job| job.id == id)
+            .ok_or_else(|| FilmanError::CommandError(format!("No job with id {}", id)))?;
+        job.progress.cancel();
+        Ok(())
+    }
+
+    /// How many jobs are still working
+    pub fn unfinished(&self) -> usize {
+        self.running.iter().filter(|job| !job.is_finished()).count()
+    }
+
+    /// Removes the jobs that have finished and returns their ids and outcomes
+    pub fn take_finished(&mut self) -> Vec<(usize, JobOutcome)> {
+        let (finished, running): (Vec<Job>, Vec<Job>) =
+            self.running.drain(..).partition(Job::is_finished);
+        self.running = running;
+        finished
+            .iter()
+            .filter_map(|job| Some((job.id, job.join()?)))
+            .collect()
+    }
+
+    /// Blocks until every job has finished and returns their ids and outcomes
+    pub fn wait(&mut self) -> Vec<(usize, JobOutcome)> {
+        self.running
+            .drain(..)
+            .filter_map(|job| Some((job.id, job.join()?)))
+            .collect()
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::FilmanError,
+    fileops::{check_failed, copy_recursive, device_of, move_recursive, remove_recursive},
+    trash::Trash,
+};
+
+/// A file operation that has been carried out and can be reversed
+#[derive(Clone, Debug)]
+pub enum Operation {
+    Rename { from: PathBuf, to: PathBuf },
+
+    /// Pairs of (source, copy)
+    Copy { created: Vec<(PathBuf, PathBuf)> },
+
+    /// Pairs of (link target, symlink)
+    Symlink { created: Vec<(PathBuf, PathBuf)> },
+
+    /// Pairs of (source, hard link)
+    Hardlink { created: Vec<(PathBuf, PathBuf)> },
+
+    /// Pairs of (old path, new path)
+    Move { moved: Vec<(PathBuf, PathBuf)> },
+
+    /// Pairs of (original path, path in the holding area)
+    Delete { held: Vec<(PathBuf, PathBuf)> },
+
+    /// Pairs of (original path, name in the trash). Undoing restores them.
+    Trash {
+        trash: Trash,
+        trashed: Vec<(PathBuf, PathBuf)>,
+    },
+
+    /// Several operations that are undone together, in reverse order
+    Batch(Vec<Operation>),
+}
+
+/// An entry of the undo stack. A background job keeps its place while it
+/// runs and gets its operation when it finishes.
+#[derive(Clone, Debug)]
+enum Entry {
+    Done(Operation),
+    Pending(usize),
+}
+
+/// Undo and redo stacks of file operations. Deleted files are moved into a
+/// holding area instead of being removed so that they can be restored. Each
+/// filesystem gets its own holding area so that holding never copies.
+#[derive(Clone, Debug)]
+pub struct Journal {
+    holding_dir: PathBuf,
+    /// Holding areas on filesystems other than `holding_dir`'s, by device
+    other_holding_dirs: HashMap<u64, PathBuf>,
+    next_held_id: usize,
+    undo: Vec<Entry>,
+    redo: Vec<Operation>,
+}
+
+impl Journal {
+    pub fn new(holding_dir: PathBuf) -> Self {
+        Journal {
+            holding_dir,
+            other_holding_dirs: HashMap::new(),
+            next_held_id: 0,
+            undo: vec![],
+            redo: vec![],
+        }
+    }
+
+    /// The holding area used by this process, inside the user cache directory
+    pub fn default_holding_dir() -> PathBuf {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir);
+        cache_dir
+            .join("filman")
+            .join("held")
+            .join(std::process::id().to_string())
+    }
+
+    /// Removes the holding areas in `dir` left behind by processes that are
+    /// no longer running, for example because they crashed
+    pub fn sweep_dead(dir: &Path) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let pid = entry.file_name().to_str().and_then(|name| name.parse().ok());
+            if pid.is_some_and(|pid| !is_running(pid)) {
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+
+    pub fn record(&mut self, operation: Operation) {
+        self.undo.push(Entry::Done(operation));
+        self.redo.clear();
+    }
+
+    /// Keeps a place for the operation of the running job `id`
+    pub fn record_pending(&mut self, id: usize) {
+        self.undo.push(Entry::Pending(id));
+        self.redo.clear();
+    }
+
+    /// Fills in the place of the finished job `id`, or drops it if the job
+    /// did nothing
+    pub fn complete(&mut self, id: usize, operation: Option<Operation>) {
+        let Some(index) = self
+            .undo
+            .iter()
+            .position(|entry| matches!(entry, Entry::Pending(pending) if *pending == id))
+        else {
+            return;
+        };
+        match operation {
+            Some(operation) => self.undo[index] = Entry::Done(operation),
+            None => {
+                self.undo.remove(index);
+            }
+        }
+    }
+
+    /// Reserves a place in the holding area for `path`. Moving it there is up
+    /// to the caller.
+    pub fn reserve_held(&mut self, path: &Path) -> Result<PathBuf, FilmanError> {
+        let filename = path.file_name().ok_or(FilmanError::PathHasNoFilename)?;
+        let dir = self.holding_dir_for(path)?.join(self.next_held_id.to_string());
+        self.next_held_id += 1;
+        fs::create_dir_all(&dir)?;
+        Ok(dir.join(filename))
+    }
+
+    /// The holding area on the filesystem of `path`, so that holding an entry
+    /// is a rename. The default one is in the cache directory and serves the
+    /// home filesystem. Other filesystems get `.filman-held-<uid>/<pid>` at
+    /// their top, like the `.Trash-<uid>` of the freedesktop trash, and
+    /// deletes there are refused if it can't be created. Like the default
+    /// area it is emptied on exit.
+    fn holding_dir_for(&mut self, path: &Path) -> Result<PathBuf, FilmanError> {
+        let device = fs::symlink_metadata(path)?.dev();
+        if device_of(&self.holding_dir) == Some(device) {
+            return Ok(self.holding_dir.clone());
+        }
+        if let Some(dir) = self.other_holding_dirs.get(&device) {
+            return Ok(dir.clone());
+        }
+
+        let parent = path.parent().ok_or(FilmanError::NoParentError)?;
+        let name = format!(".filman-held-{}", unsafe { libc::getuid() });
+        let root = filesystem_top(parent).join(name);
+        let dir = root.join(std::process::id().to_string());
+        if create_private_dir(&root).is_ok()
+            && fs::create_dir_all(&dir).is_ok()
+            && device_of(&dir) == Some(device)
+        {
+            Journal::sweep_dead(&root);
+            self.other_holding_dirs.insert(device, dir.clone());
+            return Ok(dir);
+        }
+        Err(FilmanError::CommandError(format!(
+            "Nowhere to hold {} on its filesystem, {} can not be created",
+            path.display(),
+            root.display()
+        )))
+    }
+
+    /// Removes everything in the holding areas. After this deletions can no
+    /// longer be undone.
+    pub fn discard_held(&mut self) -> Result<(), FilmanError> {
+        self.undo.retain(|entry| !matches!(entry, Entry::Done(Operation::Delete { .. })));
+        self.redo.retain(|op| !matches!(op, Operation::Delete { .. }));
+        if self.holding_dir.exists() {
+            fs::remove_dir_all(&self.holding_dir)?;
+        }
+        for (_, dir) in self.other_holding_dirs.drain() {
+            fs::remove_dir_all(&dir)?;
+            // Left alone while other processes still hold files there
+            if let Some(root) = dir.parent() {
+                let _ = fs::remove_dir(root);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the trashings after the trash was emptied, they can't be
+    /// undone any more
+    pub fn forget_trashed(&mut self) {
+        self.undo.retain(|entry| !matches!(entry, Entry::Done(Operation::Trash { .. })));
+        self.redo.retain(|op| !matches!(op, Operation::Trash { .. }));
+    }
+
+    pub fn undo(&mut self) -> Result<(), FilmanError> {
+        let operation = match self.undo.pop().ok_or(FilmanError::NothingToUndo)? {
+            Entry::Done(operation) => operation,
+            Entry::Pending(id) => {
+                self.undo.push(Entry::Pending(id));
+                return Err(FilmanError::CommandError(format!(
+                    "Job {} is still running",
+                    id
+                )));
+            }
+        };
+        // An operation that can't be reversed is dropped so that the older
+        // ones can still be undone
+        reverse(&operation)?;
+        self.redo.push(operation);
+        Ok(())
+    }
+
+    pub fn redo(&mut self) -> Result<(), FilmanError> {
+        let operation = self.redo.pop().ok_or(FilmanError::NothingToRedo)?;
+        apply(&operation)?;
+        self.undo.push(Entry::Done(operation));
+        Ok(())
+    }
+}
+
+fn apply(operation: &Operation) -> Result<(), FilmanError> {
+    let mut failed = vec![];
+    match operation {
+        Operation::Rename { from, to } => rename_no_overwrite(from, to)?,
+        Operation::Copy { created } => {
+            for (source, copy) in created {
+                copy_recursive(source, copy, &mut failed);
+            }
+        }
+        Operation::Symlink { created } => {
+            for (target, link) in created {
+                if let Err(e) = std::os::unix::fs::symlink(target, link) {
+                    failed.push((link.clone(), e));
+                }
+            }
+        }
+        Operation::Hardlink { created } => {
+            for (source, link) in created {
+                if let Err(e) = fs::hard_link(source, link) {
+                    failed.push((link.clone(), e));
+                }
+            }
+        }
+        Operation::Move { moved: pairs } | Operation::Delete { held: pairs } => {
+            for (from, to) in pairs {
+                move_recursive(from, to, &mut failed);
+            }
+        }
+        Operation::Trash { trash, trashed } => trashed
+            .iter()
+            .try_for_each(|(original, name)| trash.put_as(original, name))?,
+        Operation::Batch(operations) => operations.iter().try_for_each(apply)?,
+    }
+    check_failed(failed)
+}
+
+fn reverse(operation: &Operation) -> Result<(), FilmanError> {
+    let mut failed = vec![];
+    match operation {
+        Operation::Rename { from, to } => rename_no_overwrite(to, from)?,
+        Operation::Copy { created }
+        | Operation::Symlink { created }
+        | Operation::Hardlink { created } => {
+            for (_, copy) in created {
+                if let Err(e) = remove_recursive(copy) {
+                    failed.push((copy.clone(), e));
+                }
+            }
+        }
+        Operation::Move { moved: pairs } | Operation::Delete { held: pairs } => {
+            for (from, to) in pairs {
+                move_recursive(to, from, &mut failed);
+            }
+        }
+        Operation::Trash { trash, trashed } => trashed
+            .iter()
+            .rev()
+            .try_for_each(|(_, name)| trash.restore(name).map(|_| ()))?,
+        Operation::Batch(operations) => operations.iter().rev().try_for_each(reverse)?,
+    }
+    check_failed(failed)
+}
+
+/// The topmost directory above `dir` that is on the same filesystem
+fn filesystem_top(dir: &Path) -> PathBuf {
+    let device = device_of(dir);
+    dir.ancestors()
+        .take_while(|ancestor| device_of(ancestor) == device)
+        .last()
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// Creates `dir` readable only by the user if it doesn't exist. An existing
+/// `dir` must be a directory of the user that no one else can write to.
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != unsafe { libc::getuid() }
+        || metadata.mode() & 0o077 != 0
+    {
+        return Err(ErrorKind::PermissionDenied.into());
+    }
+    Ok(())
+}
+
+fn is_running(pid: libc::pid_t) -> bool {
+    let result = unsafe { libc::kill(pid, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+fn rename_no_overwrite(from: &Path, to: &Path) -> Result<(), FilmanError> {
+    if to.symlink_metadata().is_ok() {
+        return Err(FilmanError::FileOverwriteError(to.display().to_string()));
+    }
+    // Renames made with `:rename` may cross filesystems
+    let mut failed = vec![];
+    move_recursive(from, to, &mut failed);
+    check_failed(failed)
+}
@@ -1,7 +1,24 @@
+pub mod bulk_rename;
+pub mod columns;
 pub mod commands;
 pub mod draw;
 pub mod error;
+pub mod fileops;
+pub mod filter;
+pub mod finder;
+pub mod grep;
+pub mod hidden;
+pub mod jobs;
+pub mod journal;
+pub mod open;
+pub mod paste;
 pub mod path;
+pub mod permissions;
+pub mod rename_pattern;
+pub mod search;
+pub mod sort;
 pub mod state;
+pub mod trash;
+pub mod tokenize;
 pub mod input;
 pub mod config;
@@ -1,22 +1,38 @@
 use crossterm::{
-    event::{read, DisableMouseCapture, Event},
+    event::{poll, read, DisableMouseCapture, Event},
     execute,
     terminal::{disable_raw_mode, LeaveAlternateScreen},
 };
-use filman2::commands::{execute_command, execute_shell_command};
+use filman2::commands::{execute_command, run_shell_command, ShellOutput};
+use filman2::config::CONFIG;
 use filman2::draw::{create_terminal, draw, RenderState};
-use filman2::input::{command_mode_input, normal_mode_input, shell_mode_input, Action};
+use filman2::hidden::IgnoreCache;
+use filman2::input::{
+    command_mode_input, confirm_mode_input, conflict_mode_input, filter_mode_input,
+    find_mode_input, grep_mode_input, normal_mode_input, output_mode_input,
+    rename_preview_mode_input, search_mode_input, shell_mode_input, Action,
+};
+use filman2::jobs::Jobs;
+use filman2::journal::Journal;
+use filman2::search::SearchHistory;
 
 use std::{
     collections::{HashMap, HashSet},
     io,
+    time::Duration,
 };
 
-use filman2::state::{Mode, State};
+use filman2::state::{Mode, OutputPane, State, YankMode};
+use filman2::trash::Trash;
 
 fn main() -> Result<(), io::Error> {
     let mut terminal = create_terminal()?;
 
+    let config = &*CONFIG;
+    let holding_dir = Journal::default_holding_dir();
+    if let Some(dir) = holding_dir.parent() {
+        Journal::sweep_dead(dir);
+    }
     let pwd = std::env::current_dir()?;
     let mut selected = HashMap::new();
     selected.insert(pwd.clone(), 0);
@@ -26,16 +42,44 @@ fn main() -> Result<(), io::Error> {
         selected_in_pwd: selected,
         mode: filman2::state::Mode::NormalMode,
         yanked: HashSet::new(),
+        yank_mode: YankMode::Copy,
         multi_select: HashSet::new(),
+        journal: Journal::new(holding_dir),
+        jobs: Jobs::default(),
+        trash: Trash::new(Trash::home_trash_dir()),
+        open_handlers: config.open_handlers(),
+        paste_policy: config.paste_policy(),
+        columns: config.columns(),
+        sort: config.sort(),
+        sort_in_dir: HashMap::new(),
+        show_hidden: config.show_hidden(),
+        respect_ignore: config.respect_ignore(),
+        ignore_rules: IgnoreCache::default(),
+        filters: HashMap::new(),
+        search: None,
+        search_history: SearchHistory::load(SearchHistory::default_file()),
         error_message: None,
+        full_redraw: false,
         file_contents: Some("Example file contents".into()),
+        preview_line: None,
+        previewed_line: None,
     };
 
     // Initialize preview window
     state.sync_preview_file();
 
     'main: loop {
+        state.poll_jobs();
         state.sync_preview_file();
+        match &mut state.mode {
+            Mode::FindMode(finder) => finder.refresh(),
+            Mode::GrepMode(results) => results.refresh(),
+            _ => {}
+        }
+        if state.full_redraw {
+            terminal.clear()?;
+            state.full_redraw = false;
+        }
         let render_state: RenderState = (&state)
             .try_into()
             .expect("Failed to generate render state");
@@ -46,7 +90,19 @@ fn main() -> Result<(), io::Error> {
 
         // Every keypress is an input event and can generate 
         // zero or more actions.
-        if let Event::Key(key) = read()? {
+        // Wake up regularly while jobs are running to redraw their progress
+        // and while the finder or grep is walking to show what it found
+        let finding = match &state.mode {
+            Mode::FindMode(finder) => finder.is_walking(),
+            Mode::GrepMode(results) => results.is_searching(),
+            _ => false,
+        };
+        let event = if (state.jobs.is_empty() && !finding) || poll(Duration::from_millis(200))? {
+            Some(read()?)
+        } else {
+            None
+        };
+        if let Some(Event::Key(key)) = event {
             state.error_message = None;
             match &mut state.mode {
                 Mode::ShellCommandMode(reader) => {
@@ -55,6 +111,42 @@ fn main() -> Result<(), io::Error> {
                 Mode::CommandMode(reader) => {
                     actions.append(&mut command_mode_input(&key, reader));
                 }
+                Mode::FilterMode(reader) => {
+                    actions.append(&mut filter_mode_input(&key, reader));
+                }
+                Mode::SearchMode {
+                    reader,
+                    history_index,
+                    ..
+                } => {
+                    actions.append(&mut search_mode_input(
+                        &key,
+                        reader,
+                        history_index,
+                        &state.search_history,
+                    ));
+                }
+                Mode::FindMode(finder) => {
+                    actions.append(&mut find_mode_input(&key, finder));
+                }
+                Mode::GrepMode(results) => {
+                    actions.append(&mut grep_mode_input(&key, results));
+                }
+                Mode::ConflictMode(_) => {
+                    actions.append(&mut conflict_mode_input(&key));
+                }
+                Mode::OutputMode(pane) => {
+                    actions.append(&mut output_mode_input(&key, pane));
+                }
+                Mode::ConfirmMode {
+                    actions: on_confirm,
+                    ..
+                } => {
+                    actions.append(&mut confirm_mode_input(&key, on_confirm));
+                }
+                Mode::RenamePreviewMode { .. } => {
+                    actions.append(&mut rename_preview_mode_input(&key));
+                }
                 Mode::NormalMode => {
                     actions.append(&mut normal_mode_input(&key, &state));
                 }
@@ -70,11 +162,25 @@ fn main() -> Result<(), io::Error> {
                         state.error_message = Some(e.to_string());
                     }
                 }
-                Action::ShellCommand(cmd) => {
-                    if let Err(e) = execute_shell_command(&cmd, &state.pwd) {
+                Action::ShellCommand(cmd) => match run_shell_command(&cmd, &state) {
+                    Ok(ShellOutput { output, result }) => {
+                        // A failure is reported under the pane with its output
+                        match result {
+                            Err(e) => state.error_message = Some(e.to_string()),
+                            Ok(()) if output.trim().is_empty() => {
+                                state.error_message =
+                                    Some(format!("{} finished without output", cmd));
+                            }
+                            Ok(()) => {}
+                        }
+                        if !output.trim().is_empty() {
+                            state.mode = Mode::OutputMode(OutputPane::new(cmd, output));
+                        }
+                    }
+                    Err(e) => {
                         state.error_message = Some(e.to_string());
                     }
-                }
+                },
                 Action::ModeSwitch(mode) => {
                     state.mode = mode;
                 },
@@ -85,11 +191,43 @@ fn main() -> Result<(), io::Error> {
         }
     }
 
+    // Let running jobs finish before the holding area is removed
+    loop {
+        let unfinished = state.jobs.unfinished();
+        if unfinished == 0 {
+            break;
+        }
+        state.error_message = Some(format!("Waiting for {} jobs to finish", unfinished));
+        let render_state: RenderState = (&state)
+            .try_into()
+            .expect("Failed to generate render state");
+        draw(&render_state, &mut terminal)?;
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    let mut errors = vec![];
+    if let Err(e) = state.wait_for_jobs() {
+        errors.push(e.to_string());
+    }
+
+    if let Err(e) = state.search_history.save() {
+        errors.push(format!("Failed to save the search history: {}", e));
+    }
+
+    // Deleted files can not be restored after exiting
+    if let Err(e) = state.journal.discard_held() {
+        errors.push(format!("Failed to clean up deleted files: {}", e));
+    }
+
+    // Errors are reported after leaving the alternate screen so that they
+    // stay visible
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
         DisableMouseCapture
     )?;
+    for error in errors {
+        eprintln!("{}", error);
+    }
     Ok(())
 }
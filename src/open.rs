@@ -0,0 +1,74 @@
+use std::{fs::File, io::Read, path::Path};
+
+use crate::config::{LaunchMode, OpenHandler};
+
+/// Finds the first handler matching the extension or the sniffed MIME type of
+/// `path`. Falls back to `xdg-open`.
+pub fn find_handler(handlers: &[OpenHandler], path: &Path) -> OpenHandler {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+    let mime = sniff_mime(path);
+
+    handlers
+        .iter()
+        .find(|handler| {
+            let extension_matches = extension.as_ref().is_some_and(|extension| {
+                handler
+                    .extensions
+                    .iter()
+                    .any(|e| e.to_lowercase() == *extension)
+            });
+            let mime_matches = mime.is_some_and(|mime| {
+                handler.mime.iter().any(|pattern| mime_matches(pattern, mime))
+            });
+            extension_matches || mime_matches
+        })
+        .cloned()
+        .unwrap_or_else(|| OpenHandler {
+            extensions: vec![],
+            mime: vec![],
+            command: "xdg-open".into(),
+            mode: LaunchMode::Detached,
+        })
+}
+
+/// Matches patterns like `image/png` and `image/*`
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(prefix) => mime.split('/').next() == Some(prefix),
+        None => pattern == mime,
+    }
+}
+
+/// Guesses the MIME type from the first bytes of the file
+pub fn sniff_mime(path: &Path) -> Option<&'static str> {
+    const MAGIC: [(&[u8], &str); 9] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x7fELF", "application/x-executable"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+    ];
+
+    let mut buffer = [0; 512];
+    let len = File::open(path).and_then(|mut f| f.read(&mut buffer)).ok()?;
+    let head = &buffer[..len];
+
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(mime);
+    }
+
+    // Anything without NUL bytes that is valid UTF-8 (apart from a character
+    // cut off at the end of the buffer) is treated as text
+    let is_text = !head.contains(&0)
+        && match std::str::from_utf8(head) {
+            Ok(_) => true,
+            Err(e) => e.error_len().is_none(),
+        };
+    is_text.then_some("text/plain")
+}
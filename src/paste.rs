@@ -0,0 +1,172 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use crate::{config::ConflictPolicy, error::FilmanError, state::YankMode};
+
+/// How a single name collision is resolved
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+/// The entries a paste will create, worked out before anything is copied
+#[derive(Clone, Debug)]
+pub struct PastePlan {
+    pub yank_mode: YankMode,
+
+    /// Pairs of (source, target) that are free to paste
+    pub pairs: Vec<(PathBuf, PathBuf)>,
+
+    /// Existing entries that are replaced by the paste
+    pub replaced: Vec<PathBuf>,
+
+    /// Pairs of (source, target) where the target is already taken
+    pub conflicts: VecDeque<(PathBuf, PathBuf)>,
+}
+
+impl PastePlan {
+    /// Pairs every source with its target in `dir` and finds the collisions
+    pub fn new(
+        sources: &HashSet<PathBuf>,
+        dir: &Path,
+        yank_mode: YankMode,
+    ) -> Result<Self, FilmanError> {
+        let mut sources = sources.iter().cloned().collect::<Vec<_>>();
+        sources.sort();
+
+        let mut plan = PastePlan {
+            yank_mode,
+            pairs: vec![],
+            replaced: vec![],
+            conflicts: VecDeque::new(),
+        };
+        for source in sources {
+            let target = dir.join(source.file_name().ok_or(FilmanError::PathHasNoFilename)?);
+            // A directory pasted below itself would copy its own copy forever
+            let real_target = real_path(&target);
+            if real_target != real_path(&source) && real_target.starts_with(real_path(&source)) {
+                return Err(FilmanError::CommandError(format!(
+                    "Can not paste {} into itself",
+                    source.display()
+                )));
+            }
+            if target.symlink_metadata().is_ok() || plan.is_taken(&target) {
+                plan.conflicts.push_back((source, target));
+            } else {
+                plan.pairs.push((source, target));
+            }
+        }
+        Ok(plan)
+    }
+
+    fn is_taken(&self, target: &Path) -> bool {
+        self.pairs.iter().any(|(_, t)| t == target)
+    }
+
+    /// Resolves every conflict according to `policy`. Returns false if the
+    /// policy is to ask and there are conflicts left.
+    pub fn apply_policy(&mut self, policy: ConflictPolicy) -> Result<bool, FilmanError> {
+        match policy {
+            ConflictPolicy::Abort if !self.conflicts.is_empty() => {
+                let names = self
+                    .conflicts
+                    .iter()
+                    .map(|(_, target)| target.display().to_string())
+                    .collect::<Vec<_>>();
+                Err(FilmanError::FileOverwriteError(names.join(", ")))
+            }
+            ConflictPolicy::Abort => Ok(true),
+            ConflictPolicy::Ask => Ok(self.conflicts.is_empty()),
+            ConflictPolicy::Skip => self.resolve_all(Resolution::Skip),
+            ConflictPolicy::Overwrite => self.resolve_all(Resolution::Overwrite),
+            ConflictPolicy::Rename => self.resolve_all(Resolution::Rename),
+            ConflictPolicy::OverwriteIfNewer => {
+                while let Some((source, target)) = self.conflicts.front() {
+                    let resolution = if is_newer(source, target) {
+                        Resolution::Overwrite
+                    } else {
+                        Resolution::Skip
+                    };
+                    self.resolve_next(resolution);
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    fn resolve_all(&mut self, resolution: Resolution) -> Result<bool, FilmanError> {
+        while !self.conflicts.is_empty() {
+            self.resolve_next(resolution);
+        }
+        Ok(true)
+    }
+
+    /// Resolves the first remaining conflict
+    pub fn resolve_next(&mut self, resolution: Resolution) {
+        let Some((source, target)) = self.conflicts.pop_front() else {
+            return;
+        };
+
+        match resolution {
+            Resolution::Skip => {}
+
+            // Overwriting an entry with itself or with a directory it is in
+            // would lose it, and a target claimed by another entry in this
+            // paste can't be replaced
+            Resolution::Overwrite if is_within(&source, &target) || self.is_taken(&target) => {}
+            Resolution::Overwrite => {
+                self.replaced.push(target.clone());
+                self.pairs.push((source, target));
+            }
+            Resolution::Rename => {
+                let target = self.unique_name(&target);
+                self.pairs.push((source, target));
+            }
+        }
+    }
+
+    /// Finds a free name like `foo (1).txt` for `target`
+    fn unique_name(&self, target: &Path) -> PathBuf {
+        let stem = target
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = target
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+
+        (1..)
+            .map(|n| target.with_file_name(format!("{} ({}){}", stem, n, extension)))
+            .find(|candidate| candidate.symlink_metadata().is_err() && !self.is_taken(candidate))
+            .unwrap()
+    }
+}
+
+/// `path` with its parent resolved, so that a symlink is compared as itself
+/// rather than as what it points to
+fn real_path(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => parent
+            .canonicalize()
+            .map_or_else(|_| path.to_path_buf(), |parent| parent.join(name)),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Whether `path` is `dir` or somewhere below it
+fn is_within(path: &Path, dir: &Path) -> bool {
+    real_path(path).starts_with(real_path(dir))
+}
+
+fn is_newer(source: &Path, target: &Path) -> bool {
+    let modified = |path: &Path| path.symlink_metadata().and_then(|m| m.modified()).ok();
+    match (modified(source), modified(target)) {
+        (Some(source), Some(target)) => source > target,
+        _ => false,
+    }
+}
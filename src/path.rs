@@ -5,6 +5,8 @@ pub trait Path {
     fn filename(&self) -> Result<&str, FilmanError>;
     fn full_path_str(&self) -> Result<&str, FilmanError>;
     fn size(&self) -> Result<u64, FilmanError>;
+    fn link_target(&self) -> Option<PathBuf>;
+    fn is_dangling(&self) -> bool;
 }
 
 impl Path for PathBuf {
@@ -19,7 +21,18 @@ impl Path for PathBuf {
         self.to_str().ok_or(FilmanError::UnicodeError)
     }
 
+    /// The size of the entry itself, symlinks are not followed
     fn size(&self) -> Result<u64, FilmanError> {
-        Ok(fs::metadata(self)?.len())
+        Ok(fs::symlink_metadata(self)?.len())
+    }
+
+    /// Where the entry points if it is a symlink
+    fn link_target(&self) -> Option<PathBuf> {
+        fs::read_link(self).ok()
+    }
+
+    /// True for symlinks whose target does not exist
+    fn is_dangling(&self) -> bool {
+        self.link_target().is_some() && fs::metadata(self).is_err()
     }
 }
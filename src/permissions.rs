@@ -0,0 +1,301 @@
+use std::{
+    ffi::CString,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::error::FilmanError;
+
+/// One `[ugoa]*[+-=][rwxXst]*` clause of a symbolic mode
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Clause {
+    /// The bits the clause may touch
+    who: u32,
+    /// The bits the clause may not set, the umask when there is no `ugoa`
+    umask: u32,
+    op: char,
+    perms: String,
+}
+
+/// A mode for `:chmod`, either octal like `755` or symbolic like `u+x,go-w`.
+/// Symbolic modes without `ugoa` apply to everyone, leaving out the bits of
+/// the umask like chmod does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModeChange {
+    Octal(u32),
+    Symbolic(Vec<Clause>),
+}
+
+impl FromStr for ModeChange {
+    type Err = FilmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || FilmanError::InvalidMode(s.to_string());
+
+        if !s.is_empty() && s.len() <= 4 && s.chars().all(|c| c.is_digit(8)) {
+            return Ok(ModeChange::Octal(
+                u32::from_str_radix(s, 8).map_err(|_| invalid())?,
+            ));
+        }
+
+        let mut clauses = vec![];
+        for clause in s.split(',') {
+            let op_index = clause.find(['+', '-', '=']).ok_or_else(invalid)?;
+            let (who, rest) = clause.split_at(op_index);
+            let mut who_mask = 0;
+            for c in who.chars() {
+                who_mask |= match c {
+                    'u' => 0o4700,
+                    'g' => 0o2070,
+                    'o' => 0o1007,
+                    'a' => 0o7777,
+                    _ => return Err(invalid()),
+                };
+            }
+
+            let mut rest = rest.chars();
+            let op = rest.next().ok_or_else(invalid)?;
+            let perms = rest.collect::<String>();
+            if !perms.chars().all(|c| "rwxXst".contains(c)) {
+                return Err(invalid());
+            }
+            clauses.push(Clause {
+                who: if who_mask == 0 { 0o7777 } else { who_mask },
+                umask: if who_mask == 0 { umask() } else { 0 },
+                op,
+                perms,
+            });
+        }
+        Ok(ModeChange::Symbolic(clauses))
+    }
+}
+
+impl ModeChange {
+    /// The new permission bits for an entry that currently has `mode`. `X`
+    /// looks at the mode before any of the clauses were applied.
+    pub fn apply(&self, mode: u32, is_dir: bool) -> u32 {
+        let clauses = match self {
+            ModeChange::Octal(new) => return (mode & !0o7777) | new,
+            ModeChange::Symbolic(clauses) => clauses,
+        };
+
+        let executable = is_dir || mode & 0o111 != 0;
+        let mut mode = mode;
+        for clause in clauses {
+            let mut bits = 0;
+            for c in clause.perms.chars() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    'X' if executable => 0o111,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => 0,
+                };
+            }
+            bits &= clause.who & !clause.umask;
+
+            mode = match clause.op {
+                '+' => mode | bits,
+                '-' => mode & !bits,
+                _ => (mode & !clause.who) | bits,
+            };
+        }
+        mode
+    }
+}
+
+/// The file mode creation mask of the process
+fn umask() -> u32 {
+    // SAFETY: umask can't fail, the old mask is put back right away
+    let mask = unsafe { libc::umask(0) };
+    unsafe { libc::umask(mask) };
+    mask as u32
+}
+
+/// A new owner for `:chown`, written as `user`, `user:group` or `:group`.
+/// Names and numeric ids both work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+impl FromStr for Owner {
+    type Err = FilmanError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, group),
+            None => (s, ""),
+        };
+        let uid = match user {
+            "" => None,
+            user => Some(user_id(user).ok_or_else(|| FilmanError::UnknownUser(user.into()))?),
+        };
+        let gid = match group {
+            "" => None,
+            group => Some(group_id(group).ok_or_else(|| FilmanError::UnknownGroup(group.into()))?),
+        };
+        if uid.is_none() && gid.is_none() {
+            return Err(FilmanError::UnknownUser(s.to_string()));
+        }
+        Ok(Owner { uid, gid })
+    }
+}
+
+fn user_id(name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    let name = CString::new(name).ok()?;
+    let mut buffer = vec![0; 16384];
+    let mut passwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: every pointer is valid for the duration of the call and the
+    // buffer length is passed along
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    (status == 0 && !result.is_null()).then_some(passwd.pw_uid)
+}
+
+fn group_id(name: &str) -> Option<u32> {
+    if let Ok(id) = name.parse() {
+        return Some(id);
+    }
+    let name = CString::new(name).ok()?;
+    let mut buffer = vec![0; 16384];
+    let mut group = unsafe { std::mem::zeroed::<libc::group>() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: as in `user_id`
+    let status = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    (status == 0 && !result.is_null()).then_some(group.gr_gid)
+}
+
+/// The name of the user with id `uid`
+pub fn user_name(uid: u32) -> Option<String> {
+    let mut buffer = vec![0; 16384];
+    let mut passwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: as in `user_id`
+    let status = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    // SAFETY: pw_name points into `buffer` and is nul terminated
+    let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// The name of the group with id `gid`
+pub fn group_name(gid: u32) -> Option<String> {
+    let mut buffer = vec![0; 16384];
+    let mut group = unsafe { std::mem::zeroed::<libc::group>() };
+    let mut result = std::ptr::null_mut();
+    // SAFETY: as in `user_id`
+    let status = unsafe {
+        libc::getgrgid_r(
+            gid,
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status != 0 || result.is_null() {
+        return None;
+    }
+    // SAFETY: gr_name points into `buffer` and is nul terminated
+    let name = unsafe { std::ffi::CStr::from_ptr(group.gr_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// `path` and, if `recursive` is set, everything below it. Symlinks are not
+/// followed into.
+fn entries(path: &Path, recursive: bool, errors: &mut Vec<FilmanError>) -> Vec<PathBuf> {
+    let mut ret = vec![path.to_path_buf()];
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    if !recursive || !is_dir {
+        return ret;
+    }
+    match fs::read_dir(path) {
+        Ok(children) => {
+            for child in children {
+                match child {
+                    Ok(child) => ret.extend(entries(&child.path(), true, errors)),
+                    Err(e) => errors.push(FilmanError::ReadDirectoryError(e)),
+                }
+            }
+        }
+        Err(e) => errors.push(FilmanError::ReadDirectoryError(e)),
+    }
+    ret
+}
+
+/// Changes the permissions of every path, continuing past failures
+pub fn chmod(paths: &[PathBuf], mode: &ModeChange, recursive: bool) -> Result<(), FilmanError> {
+    let mut errors = vec![];
+    for path in paths {
+        for entry in entries(path, recursive, &mut errors) {
+            let result = fs::symlink_metadata(&entry).and_then(|metadata| {
+                // Permissions of symlinks themselves mean nothing on Linux
+                if recursive && metadata.is_symlink() {
+                    return Ok(());
+                }
+                let metadata = fs::metadata(&entry)?;
+                let new = mode.apply(metadata.permissions().mode(), metadata.is_dir());
+                fs::set_permissions(&entry, fs::Permissions::from_mode(new))
+            });
+            if let Err(source) = result {
+                errors.push(FilmanError::ChmodError {
+                    path: entry,
+                    source,
+                });
+            }
+        }
+    }
+    FilmanError::collect(errors)
+}
+
+/// Changes the owner of every path, continuing past failures. Symlinks
+/// themselves are changed rather than their targets.
+pub fn chown(paths: &[PathBuf], owner: Owner, recursive: bool) -> Result<(), FilmanError> {
+    let mut errors = vec![];
+    for path in paths {
+        for entry in entries(path, recursive, &mut errors) {
+            if let Err(source) = std::os::unix::fs::lchown(&entry, owner.uid, owner.gid) {
+                errors.push(FilmanError::ChownError {
+                    path: entry,
+                    source,
+                });
+            }
+        }
+    }
+    FilmanError::collect(errors)
+}
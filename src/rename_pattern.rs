@@ -0,0 +1,227 @@
+use std::path::PathBuf;
+
+use regex::{Captures, Regex};
+
+use crate::{bulk_rename, error::FilmanError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+    NextUpper,
+    NextLower,
+    Keep,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Group(String),
+    Counter { width: usize, zero_pad: bool },
+    Case(Case),
+}
+
+/// The replacement of `:rename_pattern`. `$1`, `${1}` and `${name}` insert
+/// capture groups, `{n}` or `{n:03}` a counter starting at 1, and `\U`, `\L`,
+/// `\u`, `\l` and `\E` change the case like in sed. `$$`, `{{`, `}}` and `\\`
+/// are literal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, FilmanError> {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        let error = |message: &str| {
+            FilmanError::CommandParseError(format!("{} in replacement {}", message, template))
+        };
+
+        while let Some(c) = chars.next() {
+            let segment = match c {
+                '$' if chars.peek() == Some(&'$') => {
+                    chars.next();
+                    literal.push('$');
+                    continue;
+                }
+                '$' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    let name = take_braced(&mut chars).ok_or_else(|| error("Unclosed ${"))?;
+                    if name.is_empty() {
+                        return Err(error("Empty group name"));
+                    }
+                    Segment::Group(name)
+                }
+                '$' => {
+                    let mut name = String::new();
+                    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                    if name.is_empty() {
+                        return Err(error("Lone $"));
+                    }
+                    Segment::Group(name)
+                }
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                    continue;
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                    continue;
+                }
+                '{' => {
+                    let spec = take_braced(&mut chars).ok_or_else(|| error("Unclosed {"))?;
+                    parse_counter(&spec).ok_or_else(|| error(&format!("Unknown {{{}}}", spec)))?
+                }
+                '\\' => match chars.next() {
+                    Some('U') => Segment::Case(Case::Upper),
+                    Some('L') => Segment::Case(Case::Lower),
+                    Some('u') => Segment::Case(Case::NextUpper),
+                    Some('l') => Segment::Case(Case::NextLower),
+                    Some('E') => Segment::Case(Case::Keep),
+                    Some('\\') => {
+                        literal.push('\\');
+                        continue;
+                    }
+                    _ => return Err(error("Unknown escape")),
+                },
+                c => {
+                    literal.push(c);
+                    continue;
+                }
+            };
+
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(segment);
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template { segments })
+    }
+
+    /// The replacement for one match, `counter` is the number of the file
+    pub fn render(&self, captures: &Captures, counter: usize) -> String {
+        let mut ret = String::new();
+        let mut case = Case::Keep;
+        let mut next = Case::Keep;
+
+        for segment in &self.segments {
+            let text = match segment {
+                Segment::Literal(text) => text.clone(),
+                Segment::Group(name) => {
+                    let group = match name.parse::<usize>() {
+                        Ok(index) => captures.get(index),
+                        Err(_) => captures.name(name),
+                    };
+                    group.map(|m| m.as_str().to_string()).unwrap_or_default()
+                }
+                Segment::Counter { width, zero_pad } if *zero_pad => {
+                    format!("{:0width$}", counter, width = width)
+                }
+                Segment::Counter { width, .. } => format!("{:width$}", counter, width = width),
+                Segment::Case(c @ (Case::NextUpper | Case::NextLower)) => {
+                    next = *c;
+                    continue;
+                }
+                Segment::Case(c) => {
+                    case = *c;
+                    continue;
+                }
+            };
+
+            let mut text = match case {
+                Case::Upper => text.to_uppercase(),
+                Case::Lower => text.to_lowercase(),
+                _ => text,
+            };
+            if let Some(first) = text.chars().next().filter(|_| next != Case::Keep) {
+                let changed = match next {
+                    Case::NextUpper => first.to_uppercase().collect::<String>(),
+                    _ => first.to_lowercase().collect::<String>(),
+                };
+                text.replace_range(..first.len_utf8(), &changed);
+                next = Case::Keep;
+            }
+            ret.push_str(&text);
+        }
+        ret
+    }
+}
+
+/// The text up to the closing `}`, which is consumed. `None` if there is none.
+fn take_braced(chars: &mut impl Iterator<Item = char>) -> Option<String> {
+    let mut text = String::new();
+    for c in chars {
+        if c == '}' {
+            return Some(text);
+        }
+        text.push(c);
+    }
+    None
+}
+
+fn parse_counter(spec: &str) -> Option<Segment> {
+    let format = match spec.strip_prefix('n')? {
+        "" => {
+            return Some(Segment::Counter {
+                width: 0,
+                zero_pad: false,
+            })
+        }
+        rest => rest.strip_prefix(':')?,
+    };
+    Some(Segment::Counter {
+        width: format.parse().ok()?,
+        zero_pad: format.starts_with('0'),
+    })
+}
+
+/// Replaces the first match of `regex` in the name of every path. Paths that
+/// don't match are left out and the counter only counts the matching ones.
+pub fn plan(
+    paths: &[PathBuf],
+    regex: &Regex,
+    template: &Template,
+) -> Result<Vec<(PathBuf, PathBuf)>, FilmanError> {
+    let mut renames = vec![];
+    let mut counter = 0;
+    for path in paths {
+        let name = path
+            .file_name()
+            .ok_or(FilmanError::PathHasNoFilename)?
+            .to_str()
+            .ok_or(FilmanError::UnicodeError)?;
+        let Some(captures) = regex.captures(name) else {
+            continue;
+        };
+        counter += 1;
+
+        let matched = captures.get(0).unwrap();
+        let new_name = format!(
+            "{}{}{}",
+            &name[..matched.start()],
+            template.render(&captures, counter),
+            &name[matched.end()..]
+        );
+        if new_name.is_empty() || new_name.contains('/') {
+            return Err(FilmanError::CommandError(format!(
+                "Invalid new name {:?} for {}",
+                new_name, name
+            )));
+        }
+        if new_name != name {
+            renames.push((path.clone(), path.with_file_name(new_name)));
+        }
+    }
+
+    bulk_rename::check(&renames)?;
+    Ok(renames)
+}
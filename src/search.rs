@@ -0,0 +1,78 @@
+use std::{fs, path::PathBuf};
+
+use crate::error::FilmanError;
+
+/// How many searches are remembered
+const HISTORY_LENGTH: usize = 100;
+
+/// Earlier searches, oldest first. If there is a file the history is loaded
+/// from it and saved back to it so that it outlives the process.
+#[derive(Clone, Debug, Default)]
+pub struct SearchHistory {
+    file: Option<PathBuf>,
+    entries: Vec<String>,
+}
+
+impl SearchHistory {
+    /// The history in `file`, or an empty history if it can't be read
+    pub fn load(file: PathBuf) -> Self {
+        let entries = fs::read_to_string(&file)
+            .map(|text| text.lines().map(String::from).collect())
+            .unwrap_or_default();
+        SearchHistory {
+            file: Some(file),
+            entries,
+        }
+    }
+
+    /// The history file in the user data directory
+    pub fn default_file() -> PathBuf {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+            .unwrap_or_else(std::env::temp_dir)
+            .join("filman")
+            .join("search_history")
+    }
+
+    /// Remembers `search` as the latest one, removing any earlier copy
+    pub fn push(&mut self, search: &str) {
+        if search.is_empty() || search.contains('\n') {
+            return;
+        }
+        self.entries.retain(|entry| entry != search);
+        self.entries.push(search.to_string());
+        if self.entries.len() > HISTORY_LENGTH {
+            self.entries.remove(0);
+        }
+    }
+
+    /// The entry `back` searches ago, where 0 is the latest
+    pub fn get(&self, back: usize) -> Option<&str> {
+        let index = self.entries.len().checked_sub(back + 1)?;
+        Some(&self.entries[index])
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn save(&self) -> Result<(), FilmanError> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut text = self.entries.join("\n");
+        text.push('\n');
+        fs::write(file, text)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    cmp::Ordering,
+    fs::{self, Metadata},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use crate::config::{SortKey, SortOrder};
+
+/// Compares names the way people count, so `file2` comes before `file10`.
+/// Case only matters when the names are otherwise equal.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_chars, mut b_chars) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let (x, y) = match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) => (*x, *y),
+        };
+
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                let mut digits = String::new();
+                while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(c);
+                }
+                digits
+            };
+            let (x, y) = (number(&mut a_chars), number(&mut b_chars));
+            let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+            let ordering = x_trimmed
+                .len()
+                .cmp(&y_trimmed.len())
+                .then_with(|| x_trimmed.cmp(y_trimmed));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        } else {
+            let ordering = x.to_lowercase().cmp(y.to_lowercase());
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+            a_chars.next();
+            b_chars.next();
+        }
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    name: String,
+    metadata: Option<Metadata>,
+    /// Links to directories are listed with the directories
+    is_dir: bool,
+}
+
+impl Entry {
+    fn size(&self) -> u64 {
+        self.metadata.as_ref().map_or(0, Metadata::len)
+    }
+
+    fn modified(&self) -> SystemTime {
+        self.metadata
+            .as_ref()
+            .and_then(|m| m.modified().ok())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+
+    fn extension(&self) -> String {
+        self.path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Directories, then files, then links, then everything else
+    fn type_rank(&self) -> u8 {
+        match &self.metadata {
+            Some(m) if m.is_dir() => 0,
+            Some(m) if m.is_file() => 1,
+            Some(m) if m.is_symlink() => 2,
+            _ => 3,
+        }
+    }
+}
+
+/// Sorts `paths` in place. Entries that compare equal by the key are ordered
+/// by name so that the order is always the same.
+pub fn sort_paths(paths: &mut Vec<PathBuf>, order: SortOrder) {
+    // Entries are only stat'ed when the order depends on it
+    let needs_metadata = matches!(order.key, SortKey::Size | SortKey::Modified | SortKey::Type);
+    let mut entries = paths
+        .drain(..)
+        .map(|path| {
+            let metadata = needs_metadata
+                .then(|| fs::symlink_metadata(&path).ok())
+                .flatten();
+            let is_dir = order.dirs_first
+                && match &metadata {
+                    Some(m) if !m.is_symlink() => m.is_dir(),
+                    _ => fs::metadata(&path).is_ok_and(|m| m.is_dir()),
+                };
+            Entry {
+                name: path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                metadata,
+                is_dir,
+                path,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    entries.sort_by(|a, b| {
+        let by_key = match order.key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size().cmp(&b.size()),
+            SortKey::Modified => a.modified().cmp(&b.modified()),
+            SortKey::Extension => natural_cmp(&a.extension(), &b.extension()),
+            SortKey::Type => a.type_rank().cmp(&b.type_rank()),
+        };
+        let ordering = by_key.then_with(|| natural_cmp(&a.name, &b.name));
+        let ordering = if order.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+
+        // Directories stay first even when reversed
+        if order.dirs_first {
+            b.is_dir.cmp(&a.is_dir).then(ordering)
+        } else {
+            ordering
+        }
+    });
+    paths.extend(entries.into_iter().map(|entry| entry.path));
+}
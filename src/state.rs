@@ -2,19 +2,110 @@ use core::fmt;
 use std::{
     collections::{HashMap, HashSet},
     fs,
+    io::{BufRead, BufReader},
     path::PathBuf,
 };
 
 use prompter::PromptReader;
 
+use crate::config::{Column, ConflictPolicy, OpenHandler, SortOrder};
 use crate::error::FilmanError;
+use crate::input::Action;
+use crate::fileops::Progress;
+use crate::jobs::{JobOutcome, Jobs};
+use crate::journal::Journal;
+use crate::paste::PastePlan;
+use crate::filter::Filter;
+use crate::finder::Finder;
+use crate::grep::GrepResults;
+use crate::hidden::{is_dotfile, IgnoreCache};
 use crate::path::Path;
+use crate::search::SearchHistory;
+use crate::sort::sort_paths;
+use crate::trash::Trash;
+
+/// Lines shown above the line a file is previewed from
+const PREVIEW_CONTEXT: usize = 2;
+
+/// Lines read when a file is previewed from a line
+const PREVIEW_LINES: usize = 200;
 
 #[derive(Clone, Debug)]
 pub enum Mode {
     NormalMode,
     CommandMode(PromptReader),
     ShellCommandMode(PromptReader),
+
+    /// Filters pwd by name while the filter is typed
+    FilterMode(PromptReader),
+
+    /// Moves the cursor to matching names while the search is typed.
+    /// `history_index` is set while browsing earlier searches. The search
+    /// and cursor from before typing are put back if the search is aborted.
+    SearchMode {
+        reader: PromptReader,
+        history_index: Option<usize>,
+        previous_search: Option<Filter>,
+        previous_cursor: usize,
+    },
+
+    /// Fuzzy finds paths below pwd in a popup
+    FindMode(Finder),
+
+    /// Lists the matches of a `:grep` in place of the files while the
+    /// preview follows the highlighted match
+    GrepMode(GrepResults),
+
+    /// Shows the output of a shell command in a popup
+    OutputMode(OutputPane),
+
+    /// Asks how to resolve the name collisions of a paste, one at a time
+    ConflictMode(PastePlan),
+
+    /// Asks a yes/no question and runs `actions` if the answer is yes
+    ConfirmMode {
+        message: String,
+        actions: Vec<Action>,
+    },
+
+    /// Shows the (old, new) names of a pattern rename in the Files pane and
+    /// applies exactly these renames if the user confirms
+    RenamePreviewMode { renames: Vec<(PathBuf, PathBuf)> },
+}
+
+/// Scrollable text shown in a popup over the file panes
+#[derive(Clone, Debug)]
+pub struct OutputPane {
+    pub title: String,
+    pub text: String,
+    pub scroll: u16,
+}
+
+impl OutputPane {
+    pub fn new(title: String, text: String) -> Self {
+        OutputPane {
+            title,
+            text,
+            scroll: 0,
+        }
+    }
+
+    pub fn scroll_down(&mut self) {
+        let max_scroll = self.text.lines().count().saturating_sub(1);
+        let max_scroll = max_scroll.min(u16::MAX as usize) as u16;
+        self.scroll = self.scroll.saturating_add(1).min(max_scroll);
+    }
+
+    pub fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+}
+
+/// Whether the yanked entries should be copied or moved on paste
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YankMode {
+    Copy,
+    Cut,
 }
 
 #[derive(Clone)]
@@ -24,9 +115,46 @@ pub struct State {
     pub mode: Mode,
 
     pub file_contents: Option<String>,
+
+    /// A file and line jumped to, previewed from that line while selected
+    pub preview_line: Option<(PathBuf, usize)>,
+
+    /// The file and line `file_contents` was read from, if it was previewed
+    /// from a line
+    pub previewed_line: Option<(PathBuf, usize)>,
     pub yanked: HashSet<PathBuf>,
+    pub yank_mode: YankMode,
     pub multi_select: HashSet<PathBuf>,
+    pub journal: Journal,
+    pub jobs: Jobs,
+    pub trash: Trash,
+    pub open_handlers: Vec<OpenHandler>,
+    pub paste_policy: ConflictPolicy,
+
+    /// Metadata columns of the Files table, in order
+    pub columns: Vec<Column>,
+
+    /// Used for directories without an order of their own in `sort_in_dir`
+    pub sort: SortOrder,
+    pub sort_in_dir: HashMap<PathBuf, SortOrder>,
+
+    /// Whether dotfiles are listed
+    pub show_hidden: bool,
+
+    /// Whether entries matched by `.gitignore` or `.ignore` files are hidden
+    pub respect_ignore: bool,
+    pub ignore_rules: IgnoreCache,
+
+    /// Name filters of directories, see `Filter`
+    pub filters: HashMap<PathBuf, Filter>,
+
+    /// The latest `/` search
+    pub search: Option<Filter>,
+    pub search_history: SearchHistory,
     pub error_message: Option<String>,
+
+    /// Set when something else has drawn on the terminal
+    pub full_redraw: bool,
 }
 
 impl fmt::Debug for State {
@@ -35,9 +163,27 @@ impl fmt::Debug for State {
             .field("pwd", &self.pwd)
             .field("selected_in_pwd", &self.selected_in_pwd)
             .field("mode", &self.mode)
+            .field("preview_line", &self.preview_line)
+            .field("previewed_line", &self.previewed_line)
             .field("yanked", &self.yanked)
+            .field("yank_mode", &self.yank_mode)
             .field("multi_select", &self.multi_select)
+            .field("journal", &self.journal)
+            .field("jobs", &self.jobs)
+            .field("trash", &self.trash)
+            .field("open_handlers", &self.open_handlers)
+            .field("paste_policy", &self.paste_policy)
+            .field("columns", &self.columns)
+            .field("sort", &self.sort)
+            .field("sort_in_dir", &self.sort_in_dir)
+            .field("show_hidden", &self.show_hidden)
+            .field("respect_ignore", &self.respect_ignore)
+            .field("ignore_rules", &self.ignore_rules)
+            .field("filters", &self.filters)
+            .field("search", &self.search)
+            .field("search_history", &self.search_history)
             .field("error_message", &self.error_message)
+            .field("full_redraw", &self.full_redraw)
             .finish()
     }
 }
@@ -58,17 +204,70 @@ impl State {
         *self.selected_in_pwd.get(&self.pwd).unwrap_or(&0)
     }
 
+    /// Moves the cursor onto the entry in pwd that `path` is in or below
+    pub fn select_path(&mut self, path: &std::path::Path) -> Result<(), FilmanError> {
+        let Ok(relative) = path.strip_prefix(&self.pwd) else {
+            return Ok(());
+        };
+        let Some(first) = relative.components().next() else {
+            return Ok(());
+        };
+        let entry = self.pwd.join(first);
+        if let Some(index) = self.files_in_pwd()?.iter().position(|p| *p == entry) {
+            self.selected_in_pwd.insert(self.pwd.clone(), index);
+        }
+        Ok(())
+    }
+
     pub fn selected_index_in_parent(&self) -> Result<Option<usize>, FilmanError> {
         let parent_files = self.files_in_parent()?;
         Ok(parent_files.into_iter().position(|x| x == self.pwd))
     }
 
+    /// How the entries of `dir` are sorted
+    pub fn sort_order(&self, dir: &std::path::Path) -> SortOrder {
+        self.sort_in_dir.get(dir).copied().unwrap_or(self.sort)
+    }
+
+    /// Removes the hidden, ignored and filtered out entries of `dir`. Pwd
+    /// itself is always kept so that it can be found in the parent listing.
+    fn filter_entries(&self, dir: &std::path::Path, paths: &mut Vec<PathBuf>) {
+        if !self.show_hidden {
+            paths.retain(|p| !is_dotfile(p) || *p == self.pwd);
+        }
+        if self.respect_ignore {
+            let rules = self.ignore_rules.rules(&self.pwd, dir);
+            paths.retain(|p| !rules.is_ignored(p) || *p == self.pwd);
+        }
+        if let Some(filter) = self.filters.get(dir) {
+            paths.retain(|p| {
+                p.filename().is_ok_and(|name| filter.matches(name)) || *p == self.pwd
+            });
+        }
+    }
+
+    /// Puts the cursor back on `selected` after the listing changed, or on
+    /// the closest index if it is no longer listed
+    pub fn keep_cursor_on(&mut self, selected: Option<PathBuf>) -> Result<(), FilmanError> {
+        let files = self.files_in_pwd()?;
+        let index = match selected.and_then(|s| files.iter().position(|p| *p == s)) {
+            Some(index) => index,
+            None => self
+                .selected_index_in_pwd()
+                .min(files.len().saturating_sub(1)),
+        };
+        self.selected_in_pwd.insert(self.pwd.clone(), index);
+        Ok(())
+    }
+
     pub fn files_in_parent(&self) -> Result<Vec<PathBuf>, FilmanError> {
         if let Some(parent) = self.pwd.parent() {
             let path_iter = fs::read_dir(parent)?;
-            let path_bufs = path_iter
+            let mut path_bufs = path_iter
                 .map(|x| x.map(|y| y.path()))
                 .collect::<Result<Vec<PathBuf>, _>>()?;
+            self.filter_entries(parent, &mut path_bufs);
+            sort_paths(&mut path_bufs, self.sort_order(parent));
             Ok(path_bufs)
         } else {
             Ok(vec![])
@@ -77,10 +276,13 @@ impl State {
 
     pub fn files_in_pwd(&self) -> Result<Vec<PathBuf>, FilmanError> {
         let paths = fs::read_dir(&self.pwd)?;
-        Ok(paths
+        let mut paths = paths
             .into_iter()
             .map(|x| x.map(|y| y.path()))
-            .collect::<std::io::Result<Vec<PathBuf>>>()?)
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        self.filter_entries(&self.pwd, &mut paths);
+        sort_paths(&mut paths, self.sort_order(&self.pwd));
+        Ok(paths)
     }
 
     pub fn filename_of_selected(&self) -> Result<String, FilmanError> {
@@ -91,6 +293,32 @@ impl State {
     }
 
     pub fn sync_preview_file(&mut self) {
+        let line = match &self.mode {
+            Mode::GrepMode(results) => results.selected(),
+            _ => self.preview_line.clone().filter(|(path, _)| {
+                self.path_of_selected().is_ok_and(|selected| selected.as_ref() == Some(path))
+            }),
+        };
+        // Files previewed from a line are shown whatever their size, so only
+        // the lines around it are read and only when the line changes
+        if let Some((path, line)) = line {
+            if self.previewed_line.as_ref() != Some(&(path.clone(), line)) {
+                let skip = line.saturating_sub(PREVIEW_CONTEXT + 1);
+                self.file_contents = fs::File::open(&path).ok().and_then(|file| {
+                    BufReader::new(file)
+                        .lines()
+                        .skip(skip)
+                        .take(PREVIEW_LINES)
+                        .collect::<Result<Vec<_>, _>>()
+                        .ok()
+                        .map(|lines| lines.join("\n"))
+                });
+                self.previewed_line = Some((path, line));
+            }
+            return;
+        }
+        self.previewed_line = None;
+
         if let Ok(Some(path)) = self.path_of_selected() {
             if let Ok(metadata) = fs::metadata(path.clone()) {
                 if metadata.len() < 2048 {
@@ -102,6 +330,42 @@ impl State {
         }
     }
 
+    /// Runs `work` as a background job. Its place in the journal is taken
+    /// now so that it keeps its order relative to other operations.
+    pub fn spawn_job<F>(&mut self, description: String, work: F)
+    where
+        F: FnOnce(&Progress) -> JobOutcome + Send + 'static,
+    {
+        let id = self.jobs.spawn(description, work);
+        self.journal.record_pending(id);
+    }
+
+    fn finish_job(&mut self, id: usize, outcome: JobOutcome) -> Result<(), FilmanError> {
+        self.journal.complete(id, outcome.operation);
+        outcome.result
+    }
+
+    /// Journals the jobs that have finished and shows their errors
+    pub fn poll_jobs(&mut self) {
+        for (id, outcome) in self.jobs.take_finished() {
+            if let Err(e) = self.finish_job(id, outcome) {
+                self.error_message = Some(e.to_string());
+            }
+        }
+    }
+
+    /// Waits for all jobs to finish and returns the first error
+    pub fn wait_for_jobs(&mut self) -> Result<(), FilmanError> {
+        let mut result = Ok(());
+        for (id, outcome) in self.jobs.wait() {
+            let job_result = self.finish_job(id, outcome);
+            if result.is_ok() {
+                result = job_result;
+            }
+        }
+        result
+    }
+
     pub fn multiselected_in_pwd(&self) -> HashSet<PathBuf> {
         self.multi_select
             .iter()
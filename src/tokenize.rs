@@ -0,0 +1,138 @@
+use std::{collections::HashMap, iter::Peekable, str::CharIndices};
+
+use crate::error::FilmanError;
+
+/// Splits a command line into arguments using shell-like rules. Whitespace
+/// separates arguments, single quotes keep everything literally, double quotes
+/// allow `\"` and `\\` escapes and a backslash outside quotes escapes the next
+/// character.
+pub fn tokenize(cmd: &str) -> Result<Vec<String>, FilmanError> {
+    tokenize_with_variables(cmd, &HashMap::new())
+}
+
+/// Like `tokenize` but also expands `$name` outside of single quotes. A
+/// variable standing alone as an unquoted word expands to one argument per
+/// value, otherwise the values are joined with spaces. Unknown variables are
+/// kept as they are.
+pub fn tokenize_with_variables(
+    cmd: &str,
+    variables: &HashMap<&str, Vec<String>>,
+) -> Result<Vec<String>, FilmanError> {
+    let mut tokens = vec![];
+    let mut current: Option<String> = None;
+    let mut chars = cmd.char_indices().peekable();
+    let column_of = |index: usize| cmd[..index].chars().count() + 1;
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(token) = current.take() {
+                    tokens.push(token);
+                }
+            }
+            '\'' => {
+                let token = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => token.push(c),
+                        None => return Err(unbalanced('\'', column_of(index))),
+                    }
+                }
+            }
+            '"' => {
+                let token = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\' | '$'))) => token.push(c),
+                            Some((_, c)) => {
+                                token.push('\\');
+                                token.push(c);
+                            }
+                            None => return Err(unbalanced('"', column_of(index))),
+                        },
+                        Some((_, '$')) => {
+                            let name = read_name(&mut chars);
+                            match variables.get(name.as_str()) {
+                                Some(values) => token.push_str(&values.join(" ")),
+                                None => token.push_str(&format!("${}", name)),
+                            }
+                        }
+                        Some((_, c)) => token.push(c),
+                        None => return Err(unbalanced('"', column_of(index))),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some((_, c)) => current.get_or_insert_with(String::new).push(c),
+                None => {
+                    return Err(FilmanError::CommandParseError(format!(
+                        "Trailing backslash at column {}",
+                        column_of(index)
+                    )))
+                }
+            },
+            '$' => {
+                let name = read_name(&mut chars);
+                let ends_word = chars.peek().is_none_or(|(_, c)| c.is_whitespace());
+                match variables.get(name.as_str()) {
+                    Some(values) if current.is_none() && ends_word => {
+                        tokens.extend(values.iter().cloned())
+                    }
+                    Some(values) => current
+                        .get_or_insert_with(String::new)
+                        .push_str(&values.join(" ")),
+                    None => current
+                        .get_or_insert_with(String::new)
+                        .push_str(&format!("${}", name)),
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if let Some(token) = current {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Whether `cmd` uses the variable `name`, that is whether
+/// `tokenize_with_variables` would expand it
+pub fn uses_variable(cmd: &str, name: &str) -> Result<bool, FilmanError> {
+    let marker = "\0";
+    let variables = HashMap::from([(name, vec![marker.to_string()])]);
+    let tokens = tokenize_with_variables(cmd, &variables)?;
+    Ok(tokens.iter().any(|token| token.contains(marker)))
+}
+
+fn read_name(chars: &mut Peekable<CharIndices>) -> String {
+    let mut name = String::new();
+    while let Some((_, c)) = chars.peek() {
+        if !(c.is_ascii_alphanumeric() || *c == '_') {
+            break;
+        }
+        name.push(*c);
+        chars.next();
+    }
+    name
+}
+
+fn unbalanced(quote: char, column: usize) -> FilmanError {
+    FilmanError::CommandParseError(format!(
+        "Unbalanced {} quote starting at column {}",
+        quote, column
+    ))
+}
+
+/// Quotes `arg` so that `tokenize` turns it back into a single argument
+pub fn quote(arg: &str) -> String {
+    let is_safe = |c: char| c.is_alphanumeric() || "_-./:,+@%=".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
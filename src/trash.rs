@@ -0,0 +1,242 @@
+use std::{
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::FilmanError,
+    fileops::{check_failed, device_of, move_recursive, remove_recursive},
+};
+
+/// A trash directory following the freedesktop.org Trash specification. Trashed
+/// files are kept in `files/` and the `.trashinfo` file with the original path
+/// and deletion date is kept in `info/`.
+#[derive(Clone, Debug)]
+pub struct Trash {
+    dir: PathBuf,
+}
+
+impl Trash {
+    pub fn new(dir: PathBuf) -> Self {
+        Trash { dir }
+    }
+
+    /// The home trash, `$XDG_DATA_HOME/Trash`
+    pub fn home_trash_dir() -> PathBuf {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+            .unwrap_or_else(std::env::temp_dir)
+            .join("Trash")
+    }
+
+    pub fn files_dir(&self) -> PathBuf {
+        self.dir.join("files")
+    }
+
+    fn info_dir(&self) -> PathBuf {
+        self.dir.join("info")
+    }
+
+    fn info_path(&self, name: &OsStr) -> PathBuf {
+        let mut info_name = name.to_os_string();
+        info_name.push(".trashinfo");
+        self.info_dir().join(info_name)
+    }
+
+    /// Moves `path` to the trash and returns the name it was given there.
+    /// A symlink is trashed itself, not its target. Entries on another
+    /// filesystem than the trash are refused rather than copied.
+    pub fn put(&self, path: &Path) -> Result<PathBuf, FilmanError> {
+        let filename = path.file_name().ok_or(FilmanError::PathHasNoFilename)?;
+        let path = self.prepare(path)?;
+
+        // The info file is created first and exclusively, which reserves the name
+        let mut name = filename.to_os_string();
+        let mut n = 1;
+        let info_file = loop {
+            if self.files_dir().join(&name).symlink_metadata().is_err() {
+                match File::options()
+                    .write(true)
+                    .create_new(true)
+                    .open(self.info_path(&name))
+                {
+                    Ok(file) => break file,
+                    Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            n += 1;
+            name = filename.to_os_string();
+            name.push(format!(".{}", n));
+        };
+
+        self.move_in(&path, &name, info_file)?;
+        Ok(PathBuf::from(name))
+    }
+
+    /// Moves `path` to the trash under `name`, which has to be free. Used to
+    /// trash an entry again after its trashing was undone.
+    pub fn put_as(&self, path: &Path, name: &Path) -> Result<(), FilmanError> {
+        let path = self.prepare(path)?;
+        let taken = || FilmanError::FileOverwriteError(name.display().to_string());
+        if self.files_dir().join(name).symlink_metadata().is_ok() {
+            return Err(taken());
+        }
+        let info_file = match File::options()
+            .write(true)
+            .create_new(true)
+            .open(self.info_path(name.as_os_str()))
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(taken()),
+            Err(e) => return Err(e.into()),
+        };
+        self.move_in(&path, name.as_os_str(), info_file)
+    }
+
+    /// The canonical path of `path` after checking that it can be trashed
+    fn prepare(&self, path: &Path) -> Result<PathBuf, FilmanError> {
+        let filename = path.file_name().ok_or(FilmanError::PathHasNoFilename)?;
+        let parent = path.parent().ok_or(FilmanError::NoParentError)?;
+        let path = parent.canonicalize()?.join(filename);
+        if Some(fs::symlink_metadata(&path)?.dev()) != device_of(&self.dir) {
+            return Err(FilmanError::CommandError(format!(
+                "{} is on another filesystem than the trash",
+                path.display()
+            )));
+        }
+        fs::create_dir_all(self.files_dir())?;
+        fs::create_dir_all(self.info_dir())?;
+        Ok(path)
+    }
+
+    /// Fills in the reserved `info_file` and moves `path` to `name`. The info
+    /// file is removed again if that fails.
+    fn move_in(&self, path: &Path, name: &OsStr, mut info_file: File) -> Result<(), FilmanError> {
+        let info = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode(path.as_os_str()),
+            deletion_date()
+        );
+        let mut failed = vec![];
+        if let Err(e) = info_file.write_all(info.as_bytes()) {
+            failed.push((path.to_path_buf(), e));
+        } else {
+            move_recursive(path, &self.files_dir().join(name), &mut failed);
+        }
+
+        if !failed.is_empty() {
+            let _ = fs::remove_file(self.info_path(name));
+        }
+        check_failed(failed)
+    }
+
+    /// Moves the trashed entry `name` back to where it was deleted from
+    pub fn restore(&self, name: &Path) -> Result<PathBuf, FilmanError> {
+        let info_path = self.info_path(name.as_os_str());
+        let info = fs::read_to_string(&info_path)?;
+        let original = info
+            .lines()
+            .find_map(|line| line.strip_prefix("Path="))
+            .map(percent_decode)
+            .ok_or_else(|| {
+                FilmanError::CommandError(format!("Invalid trash info for {}", name.display()))
+            })?;
+
+        if original.symlink_metadata().is_ok() {
+            return Err(FilmanError::FileOverwriteError(
+                original.display().to_string(),
+            ));
+        }
+        if let Some(parent) = original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut failed = vec![];
+        move_recursive(&self.files_dir().join(name), &original, &mut failed);
+        check_failed(failed)?;
+        fs::remove_file(info_path)?;
+        Ok(original)
+    }
+
+    /// Permanently removes everything in the trash
+    pub fn empty(&self) -> Result<(), FilmanError> {
+        let mut failed = vec![];
+        for dir in [self.files_dir(), self.info_dir()] {
+            if !dir.exists() {
+                continue;
+            }
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if let Err(e) = remove_recursive(&path) {
+                    failed.push((path, e));
+                }
+            }
+        }
+        check_failed(failed)
+    }
+}
+
+fn percent_encode(path: &OsStr) -> String {
+    path.as_bytes()
+        .iter()
+        .map(|&b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn percent_decode(encoded: &str) -> PathBuf {
+    let bytes = encoded.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsStr::from_bytes(&decoded))
+}
+
+/// The current local time formatted as `YYYY-MM-DDThh:mm:ss`
+fn deletion_date() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0) as libc::time_t;
+
+    // SAFETY: localtime_r only writes to the tm struct we pass in
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
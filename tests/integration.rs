@@ -1,12 +1,37 @@
 use filman2::{
-    commands::{execute_command, execute_shell_command},
-    state::State,
+    bulk_rename,
+    commands::{execute_command, execute_shell_command, run_shell_command},
+    config::{Column, Config, ConflictPolicy, LaunchMode, OpenHandler, SortKey, SortOrder},
+    error::FilmanError,
+    fileops::{move_by_copy, tree_summary_up_to, Progress},
+    finder::Finder,
+    hidden::IgnoreCache,
+    input::{
+        filter_mode_input, find_mode_input, grep_mode_input, normal_mode_input,
+        output_mode_input, rename_preview_mode_input, search_mode_input, Action,
+    },
+    jobs::{JobOutcome, Jobs},
+    journal::{Journal, Operation},
+    path::Path,
+    search::SearchHistory,
+    state::{Mode, OutputPane, State},
+    tokenize::{quote, tokenize, uses_variable},
+    trash::Trash,
 };
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use prompter::PromptReader;
 use serial_test::serial;
 use std::{
     collections::{HashMap, HashSet},
-    fs::{create_dir, remove_dir_all},
+    fs::{
+        create_dir, metadata, read_dir, read_link, read_to_string, remove_dir_all, symlink_metadata,
+    },
+    os::unix::{
+        fs::{MetadataExt, PermissionsExt},
+        net::UnixListener,
+    },
     path::PathBuf,
+    sync::atomic::Ordering,
 };
 
 struct TestContext {
@@ -18,7 +43,7 @@ impl TestContext {
     fn new_with_test_dir(dir: PathBuf) -> TestContext {
         let pwd = std::env::current_dir().unwrap();
         let test_dir = pwd.join(dir);
-        if let Ok(_) = remove_dir_all(test_dir.clone()) {
+        if remove_dir_all(test_dir.clone()).is_ok() {
             eprintln!("Removing old test dir, this could happen if you have run the test suite but it failed to clean up (maybe some test failed).");
         }
         create_dir(test_dir.clone()).unwrap();
@@ -30,9 +55,27 @@ impl TestContext {
             selected_in_pwd: selected,
             mode: filman2::state::Mode::NormalMode,
             yanked: HashSet::new(),
+            yank_mode: filman2::state::YankMode::Copy,
             multi_select: HashSet::new(),
+            journal: Journal::new(pwd.join("test_env_held")),
+            jobs: Jobs::default(),
+            trash: Trash::new(pwd.join("test_env_trash")),
+            open_handlers: vec![],
+            paste_policy: ConflictPolicy::Abort,
+            columns: vec![Column::Size],
+            sort: SortOrder::default(),
+            sort_in_dir: HashMap::new(),
+            show_hidden: true,
+            respect_ignore: false,
+            ignore_rules: IgnoreCache::default(),
+            filters: HashMap::new(),
+            search: None,
+            search_history: SearchHistory::default(),
             error_message: None,
+            full_redraw: false,
             file_contents: Some("Example file contents".into()),
+            preview_line: None,
+            previewed_line: None,
         };
 
         assert_eq!(state.selected_index_in_pwd(), 0);
@@ -51,6 +94,10 @@ impl TestContext {
 
 impl Drop for TestContext {
     fn drop(&mut self) {
+        if let Err(e) = self.state.journal.discard_held() {
+            println!("Not removing held files: {:?}", e);
+        }
+        let _ = remove_dir_all(self.directory.with_file_name("test_env_trash"));
         if let Err(e) = remove_dir_all(self.directory.clone()) {
             println!("Not removing old test env: {:?}", e);
         }
@@ -61,7 +108,7 @@ impl Drop for TestContext {
 #[serial]
 fn test_create_file() {
     let ctx = TestContext::new();
-    execute_shell_command("!touch test.txt", &ctx.state.pwd).unwrap();
+    execute_shell_command("!touch test.txt", &ctx.state).unwrap();
     assert!(!ctx.state.files_in_pwd().unwrap().is_empty());
 }
 
@@ -69,10 +116,11 @@ fn test_create_file() {
 #[serial]
 fn test_crate_delete_file() {
     let mut ctx = TestContext::new();
-    execute_shell_command("!touch test.txt", &ctx.state.pwd).unwrap();
+    execute_shell_command("!touch test.txt", &ctx.state).unwrap();
     assert!(!ctx.state.files_in_pwd().unwrap().is_empty());
 
     execute_command(":delete test.txt", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
     assert!(ctx.state.files_in_pwd().unwrap().is_empty());
 }
 
@@ -80,10 +128,10 @@ fn test_crate_delete_file() {
 #[serial]
 fn test_rename_file() {
     let mut ctx = TestContext::new();
-    execute_shell_command("!touch test.txt", &ctx.state.pwd).unwrap();
+    execute_shell_command("!touch test.txt", &ctx.state).unwrap();
     assert!(!ctx.state.files_in_pwd().unwrap().is_empty());
 
-    execute_command(":rename test2.txt", &mut ctx.state).unwrap();
+    execute_command(":rename test.txt test2.txt", &mut ctx.state).unwrap();
     let files = ctx.state.files_in_pwd().unwrap();
     let file_names: Vec<&str> = files
         .iter()
@@ -93,19 +141,691 @@ fn test_rename_file() {
     assert_eq!(file_names, vec!["test2.txt"]);
 }
 
+#[test]
+#[serial]
+fn bulk_rename_test() {
+    let mut ctx = TestContext::new();
+    for name in ["a", "b", "c", "d"] {
+        std::fs::write(ctx.directory.join(name), name).unwrap();
+    }
+    let mut paths = ctx.state.files_in_pwd().unwrap();
+    paths.sort();
+    assert_eq!(bulk_rename::names_text(&paths).unwrap(), "a\nb\nc\nd\n");
+
+    // Duplicate targets and existing entries are refused up front
+    assert!(bulk_rename::parse_edited(&paths, "a\nb\nx\nx\n").is_err());
+    assert!(bulk_rename::parse_edited(&paths, "a\nb\nc\na\n").is_err());
+    assert!(bulk_rename::parse_edited(&paths, "a\nb\n").is_err());
+
+    // Names can not leave the directory
+    for name in ["../x", "sub/x", "/etc/x", ".", ".."] {
+        let edited = format!("a\nb\nc\n{}\n", name);
+        assert!(bulk_rename::parse_edited(&paths, &edited).is_err());
+    }
+
+    // A swap and a cycle
+    let renames = bulk_rename::parse_edited(&paths, "b\na\nd\ne\n").unwrap();
+    let operation = bulk_rename::apply(&renames).unwrap();
+    ctx.state.journal.record(operation);
+    let contents = |ctx: &TestContext, name: &str| {
+        std::fs::read_to_string(ctx.directory.join(name)).unwrap()
+    };
+    assert_eq!(contents(&ctx, "a"), "b");
+    assert_eq!(contents(&ctx, "b"), "a");
+    assert_eq!(contents(&ctx, "d"), "c");
+    assert_eq!(contents(&ctx, "e"), "d");
+    assert_eq!(ctx.state.files_in_pwd().unwrap().len(), 4);
+
+    execute_command(":undo", &mut ctx.state).unwrap();
+    for name in ["a", "b", "c", "d"] {
+        assert_eq!(contents(&ctx, name), name);
+    }
+}
+
+#[test]
+#[serial]
+fn rename_pattern_test() {
+    let mut ctx = TestContext::new();
+    for name in ["b.txt", "a.txt", "notes.md"] {
+        std::fs::write(ctx.directory.join(name), name).unwrap();
+    }
+
+    // Nothing is renamed before the preview is confirmed
+    execute_command(r":rename_pattern '(\w+)\.txt' '\U$1\E_{n:03}.txt'", &mut ctx.state).unwrap();
+    let Mode::RenamePreviewMode { renames } = ctx.state.mode.clone() else {
+        panic!("Expected a rename preview");
+    };
+    let names = renames
+        .iter()
+        .map(|(old, new)| {
+            let name = |p: &PathBuf| p.file_name().unwrap().to_str().unwrap().to_string();
+            (name(old), name(new))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            ("a.txt".to_string(), "A_001.txt".to_string()),
+            ("b.txt".to_string(), "B_002.txt".to_string())
+        ]
+    );
+    assert!(ctx.directory.join("a.txt").exists());
+
+    // Confirming applies the previewed renames even if the entries changed
+    std::fs::write(ctx.directory.join("c.txt"), "c.txt").unwrap();
+    let actions = rename_preview_mode_input(&KeyEvent::from(KeyCode::Char('y')));
+    let [Action::Command(confirm)] = actions.as_slice() else {
+        panic!("Expected a single command");
+    };
+    execute_command(confirm, &mut ctx.state).unwrap();
+    assert!(ctx.directory.join("c.txt").exists());
+    assert_eq!(read_to_string(ctx.directory.join("A_001.txt")).unwrap(), "a.txt");
+    assert_eq!(read_to_string(ctx.directory.join("B_002.txt")).unwrap(), "b.txt");
+    assert!(ctx.directory.join("notes.md").exists());
+
+    // Two names mapping to the same target are refused
+    assert!(execute_command(":rename_pattern -y '.*' same", &mut ctx.state).is_err());
+
+    // Unclosed groups and counters are parse errors
+    for template in ["'${1'", "'x_{n:03'"] {
+        let cmd = format!(":rename_pattern -y '(.*)' {}", template);
+        let err = execute_command(&cmd, &mut ctx.state).unwrap_err();
+        assert!(matches!(err, FilmanError::CommandParseError(m) if m.contains("Unclosed")));
+    }
+}
+
+#[test]
+#[serial]
+fn mkdir_touch_test() {
+    let mut ctx = TestContext::new();
+    for name in ["a", "b", "c"] {
+        std::fs::write(ctx.directory.join(name), name).unwrap();
+    }
+
+    execute_command(":mkdir 'new dir/with/parents' other", &mut ctx.state).unwrap();
+    assert!(ctx.directory.join("new dir/with/parents").is_dir());
+    assert!(ctx.directory.join("other").is_dir());
+    let selected = ctx.state.path_of_selected().unwrap().unwrap();
+    assert_eq!(selected, ctx.directory.join("other"));
+
+    execute_command(":touch 'new dir/file.txt' b", &mut ctx.state).unwrap();
+    assert!(ctx.directory.join("new dir/file.txt").is_file());
+    assert_eq!(read_to_string(ctx.directory.join("b")).unwrap(), "b");
+    let selected = ctx.state.path_of_selected().unwrap().unwrap();
+    assert_eq!(selected, ctx.directory.join("b"));
+
+    assert!(execute_command(":touch missing/file.txt", &mut ctx.state).is_err());
+}
+
+#[test]
+#[serial]
+fn paste_links_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!mkdir to", &ctx.state).unwrap();
+    std::fs::write(ctx.directory.join("test.txt"), "0123456789").unwrap();
+    execute_command(":yank test.txt", &mut ctx.state).unwrap();
+
+    ctx.state.pwd = ctx.directory.join("to");
+    execute_command(":paste_symlink -r", &mut ctx.state).unwrap();
+    let link = ctx.directory.join("to/test.txt");
+    assert_eq!(read_link(&link).unwrap(), PathBuf::from("../test.txt"));
+    assert_eq!(link.link_target(), Some(PathBuf::from("../test.txt")));
+    assert_eq!(link.size().unwrap(), "../test.txt".len() as u64);
+
+    // Linking again would overwrite the link
+    assert!(execute_command(":paste_hardlink", &mut ctx.state).is_err());
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert!(link.symlink_metadata().is_err());
+
+    execute_command(":paste_symlink", &mut ctx.state).unwrap();
+    assert_eq!(read_link(&link).unwrap(), ctx.directory.join("test.txt"));
+    std::fs::remove_file(ctx.directory.join("test.txt")).unwrap();
+    assert!(link.is_dangling());
+    std::fs::remove_file(&link).unwrap();
+
+    std::fs::write(ctx.directory.join("test.txt"), "0123456789").unwrap();
+    execute_command(":paste_hardlink", &mut ctx.state).unwrap();
+    std::fs::write(&link, "changed").unwrap();
+    assert_eq!(read_to_string(ctx.directory.join("test.txt")).unwrap(), "changed");
+}
+
+#[test]
+#[serial]
+fn chmod_chown_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!mkdir -p dir/sub", &ctx.state).unwrap();
+    execute_shell_command("!touch dir/sub/file a b", &ctx.state).unwrap();
+    let mode = |ctx: &TestContext, name: &str| {
+        metadata(ctx.directory.join(name)).unwrap().permissions().mode() & 0o7777
+    };
+
+    execute_command(":chmod 640 a", &mut ctx.state).unwrap();
+    assert_eq!(mode(&ctx, "a"), 0o640);
+    execute_command(":chmod u+x,g-r,o=r a", &mut ctx.state).unwrap();
+    assert_eq!(mode(&ctx, "a"), 0o704);
+
+    // The selection is used when no paths are given
+    ctx.state.multi_select.insert(ctx.directory.join("a"));
+    ctx.state.multi_select.insert(ctx.directory.join("b"));
+    execute_command(":chmod a=r", &mut ctx.state).unwrap();
+    assert_eq!(mode(&ctx, "a"), 0o444);
+    assert_eq!(mode(&ctx, "b"), 0o444);
+
+    // Without ugoa the umask is left out, X looks at the mode from before
+    let umask = execute_shell_command("!sh -c umask", &ctx.state).unwrap();
+    let umask = u32::from_str_radix(umask.trim(), 8).unwrap();
+    execute_command(":chmod 000 a", &mut ctx.state).unwrap();
+    execute_command(":chmod +w a", &mut ctx.state).unwrap();
+    assert_eq!(mode(&ctx, "a"), 0o222 & !umask);
+    execute_command(":chmod 600 a", &mut ctx.state).unwrap();
+    execute_command(":chmod u+x,g+X a", &mut ctx.state).unwrap();
+    assert_eq!(mode(&ctx, "a"), 0o700);
+    execute_command(":chmod g+X dir", &mut ctx.state).unwrap();
+    assert_eq!(mode(&ctx, "dir") & 0o010, 0o010);
+
+    execute_command(":chmod -R go-rwx dir", &mut ctx.state).unwrap();
+    assert_eq!(mode(&ctx, "dir/sub") & 0o077, 0);
+    assert_eq!(mode(&ctx, "dir/sub/file") & 0o077, 0);
+
+    assert!(matches!(
+        execute_command(":chmod u+q a", &mut ctx.state),
+        Err(FilmanError::InvalidMode(_))
+    ));
+    let error = execute_command(":chmod 600 missing other", &mut ctx.state).unwrap_err();
+    let FilmanError::MultipleErrors(errors) = error else {
+        panic!("Expected an error per file");
+    };
+    assert!(errors
+        .iter()
+        .all(|e| matches!(e, FilmanError::ChmodError { .. })));
+
+    // Giving a file to its current owner always works
+    let uid = metadata(ctx.directory.join("a")).unwrap().uid();
+    let gid = metadata(ctx.directory.join("a")).unwrap().gid();
+    execute_command(&format!(":chown -R {}:{} dir", uid, gid), &mut ctx.state).unwrap();
+    assert!(matches!(
+        execute_command(":chown no_such_user_here a", &mut ctx.state),
+        Err(FilmanError::UnknownUser(_))
+    ));
+}
+
+#[test]
+#[serial]
+fn metadata_columns_test() {
+    let ctx = TestContext::new();
+    let setup = "mkdir dir && touch a && chmod 4750 a && ln a b && ln -s a c";
+    execute_shell_command(&format!("!sh -c '{}'", setup), &ctx.state).unwrap();
+    let value = |column: Column, name: &str| column.value(&ctx.directory.join(name));
+
+    assert_eq!(value(Column::Permissions, "a"), "-rwsr-x---");
+    assert_eq!(value(Column::Permissions, "dir").chars().next(), Some('d'));
+    assert_eq!(value(Column::Links, "a"), "2");
+    assert_eq!(value(Column::Type, "dir"), "dir");
+    assert_eq!(value(Column::Type, "c"), "link");
+    // A symlink has its own size, the target is shown in the info column
+    assert_eq!(value(Column::Size, "c"), "1 B");
+    assert_eq!(value(Column::Inode, "a"), value(Column::Inode, "b"));
+    assert_eq!(value(Column::Modified, "a").len(), "2024-01-01 00:00".len());
+
+    // Columns come from the config and default to the size
+    let config_file = ctx.directory.join("config.json");
+    std::fs::write(
+        &config_file,
+        r#"{"keys": {"simple": {}}, "columns": ["inode", "links", "type"]}"#,
+    )
+    .unwrap();
+    let config = Config::new(config_file.clone());
+    assert_eq!(config.columns(), vec![Column::Inode, Column::Links, Column::Type]);
+    std::fs::write(&config_file, r#"{"keys": {"simple": {}}}"#).unwrap();
+    assert_eq!(Config::new(config_file).columns(), vec![Column::Size]);
+}
+
+#[test]
+#[serial]
+fn sort_test() {
+    let mut ctx = TestContext::new();
+    std::fs::write(ctx.directory.join("file10.txt"), "1").unwrap();
+    std::fs::write(ctx.directory.join("file2.rs"), "123").unwrap();
+    std::fs::write(ctx.directory.join("File1.md"), "12").unwrap();
+    create_dir(ctx.directory.join("z_dir")).unwrap();
+    let names = |ctx: &TestContext| {
+        ctx.state
+            .files_in_pwd()
+            .unwrap()
+            .iter()
+            .map(|x| x.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(names(&ctx), ["z_dir", "File1.md", "file2.rs", "file10.txt"]);
+
+    // The cursor follows the selected entry
+    ctx.state.selected_in_pwd.insert(ctx.directory.clone(), 1);
+    execute_command(":sort size dirs_first", &mut ctx.state).unwrap();
+    assert_eq!(ctx.state.sort_order(&ctx.directory).key, SortKey::Size);
+    let mut sized = names(&ctx);
+    sized.retain(|name| name != "z_dir");
+    assert_eq!(sized, ["file10.txt", "File1.md", "file2.rs"]);
+    assert_eq!(ctx.state.filename_of_selected().unwrap(), "File1.md");
+
+    execute_command(":sort extension dirs_first reverse", &mut ctx.state).unwrap();
+    assert_eq!(names(&ctx), ["z_dir", "file10.txt", "file2.rs", "File1.md"]);
+
+    // Other directories keep the default order
+    assert_eq!(ctx.state.sort_order(&ctx.directory.join("z_dir")), SortOrder::default());
+    assert!(execute_command(":sort colour", &mut ctx.state).is_err());
+}
+
+#[test]
+#[serial]
+fn hidden_and_ignored_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!mkdir -p sub/build", &ctx.state).unwrap();
+    execute_shell_command("!touch .hidden a.log b c sub/d.log sub/keep.log", &ctx.state).unwrap();
+    std::fs::write(ctx.directory.join(".gitignore"), "*.log\nbuild/\n").unwrap();
+    std::fs::write(ctx.directory.join("sub/.ignore"), "!keep.log\n").unwrap();
+    let names = |ctx: &TestContext| {
+        ctx.state
+            .files_in_pwd()
+            .unwrap()
+            .iter()
+            .map(|x| x.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&ctx), ["sub", ".gitignore", ".hidden", "a.log", "b", "c"]);
+
+    // The cursor stays on the same entry when the listing changes
+    ctx.state.selected_in_pwd.insert(ctx.directory.clone(), 4);
+    execute_command(":toggle_hidden", &mut ctx.state).unwrap();
+    assert_eq!(names(&ctx), ["sub", "a.log", "b", "c"]);
+    assert_eq!(ctx.state.filename_of_selected().unwrap(), "b");
+
+    execute_command(":toggle_ignored", &mut ctx.state).unwrap();
+    assert_eq!(names(&ctx), ["sub", "b", "c"]);
+    assert_eq!(ctx.state.filename_of_selected().unwrap(), "b");
+
+    // Rules from further up apply, and deeper files can override them
+    ctx.state.pwd = ctx.directory.join("sub");
+    assert_eq!(names(&ctx), ["keep.log"]);
+
+    // The cursor moves to the closest entry if its entry is hidden
+    ctx.state.pwd = ctx.directory.clone();
+    execute_command(":toggle_ignored", &mut ctx.state).unwrap();
+    ctx.state.selected_in_pwd.insert(ctx.directory.clone(), 3);
+    execute_command(":toggle_ignored", &mut ctx.state).unwrap();
+    assert_eq!(ctx.state.filename_of_selected().unwrap(), "c");
+
+    // Changed rules are read again
+    assert_eq!(names(&ctx), ["sub", "b", "c"]);
+    std::fs::write(ctx.directory.join(".gitignore"), "*.log\nbuild/\nc\n").unwrap();
+    assert_eq!(names(&ctx), ["sub", "b"]);
+    std::fs::write(ctx.directory.join("sub/.ignore"), "keep.log\n").unwrap();
+    ctx.state.pwd = ctx.directory.join("sub");
+    assert!(names(&ctx).is_empty());
+
+    // Files above a repository don't apply inside it
+    execute_shell_command("!mkdir -p repo/.git", &ctx.state).unwrap();
+    execute_shell_command("!touch repo/x.log", &ctx.state).unwrap();
+    ctx.state.pwd = ctx.directory.join("sub/repo");
+    assert_eq!(names(&ctx), ["x.log"]);
+}
+
+#[test]
+#[serial]
+fn filter_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!touch main.rs lib.rs README.md notes.txt", &ctx.state).unwrap();
+    let names = |ctx: &TestContext| {
+        ctx.state
+            .files_in_pwd()
+            .unwrap()
+            .iter()
+            .map(|x| x.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // Typing updates the filter after every key
+    let mut reader = PromptReader::new_with_placeholder("", None);
+    for c in "re".chars() {
+        let key = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        for action in filter_mode_input(&key, &mut reader) {
+            if let Action::Command(cmd) = action {
+                execute_command(&cmd, &mut ctx.state).unwrap();
+            }
+        }
+    }
+    assert_eq!(names(&ctx), ["README.md"]);
+
+    execute_command(":filter '*.rs'", &mut ctx.state).unwrap();
+    assert_eq!(names(&ctx), ["lib.rs", "main.rs"]);
+    execute_command(r":filter 're:^(main|notes)\.'", &mut ctx.state).unwrap();
+    assert_eq!(names(&ctx), ["main.rs", "notes.txt"]);
+    assert!(execute_command(":filter 're:('", &mut ctx.state).is_err());
+
+    // The stored filter is shown and edited as it was typed
+    let filter = ctx.state.filters[&ctx.state.pwd].to_string();
+    assert_eq!(filter, r"re:^(main|notes)\.");
+
+    // Filters belong to their directory
+    execute_shell_command("!mkdir sub", &ctx.state).unwrap();
+    ctx.state.pwd = ctx.directory.join("sub");
+    assert!(!ctx.state.filters.contains_key(&ctx.state.pwd));
+    ctx.state.pwd = ctx.directory.clone();
+    assert_eq!(names(&ctx), ["main.rs", "notes.txt"]);
+
+    execute_command(":filter", &mut ctx.state).unwrap();
+    assert_eq!(names(&ctx).len(), 5);
+}
+
+#[test]
+#[serial]
+fn search_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!touch alpha.rs beta.txt gamma.rs delta.md", &ctx.state).unwrap();
+    let selected = |ctx: &TestContext| ctx.state.filename_of_selected().unwrap();
+    // Sorted: alpha.rs beta.txt delta.md gamma.rs
+
+    // The cursor follows the typing and other entries stay listed
+    let mut reader = PromptReader::new_with_placeholder("", None);
+    let mut history_index = None;
+    for c in "rs".chars() {
+        let key = KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        let history = ctx.state.search_history.clone();
+        for action in search_mode_input(&key, &mut reader, &mut history_index, &history) {
+            if let Action::Command(cmd) = action {
+                execute_command(&cmd, &mut ctx.state).unwrap();
+            }
+        }
+    }
+    assert_eq!(selected(&ctx), "alpha.rs");
+    assert_eq!(ctx.state.files_in_pwd().unwrap().len(), 4);
+    assert!(ctx.state.search_history.is_empty());
+
+    execute_command(":search '*.rs'", &mut ctx.state).unwrap();
+    execute_command(":search_next", &mut ctx.state).unwrap();
+    assert_eq!(selected(&ctx), "gamma.rs");
+    execute_command(":search_next", &mut ctx.state).unwrap();
+    assert_eq!(selected(&ctx), "alpha.rs");
+    execute_command(":search_prev", &mut ctx.state).unwrap();
+    assert_eq!(selected(&ctx), "gamma.rs");
+
+    assert!(execute_command(":search nothing", &mut ctx.state).is_err());
+
+    // Up recalls the latest search
+    let key = KeyEvent::new(KeyCode::Up, KeyModifiers::NONE);
+    let mut reader = PromptReader::new_with_placeholder("", None);
+    let history = ctx.state.search_history.clone();
+    search_mode_input(&key, &mut reader, &mut None, &history);
+    assert_eq!(reader.result(), "nothing");
+    assert_eq!(history.get(1), Some("*.rs"));
+
+    // Esc puts back the search and the cursor from before typing
+    execute_command(":search delta", &mut ctx.state).unwrap();
+    let press = |ctx: &mut TestContext, code: KeyCode| {
+        let key = KeyEvent::new(code, KeyModifiers::NONE);
+        let actions = match &mut ctx.state.mode {
+            Mode::SearchMode {
+                reader,
+                history_index,
+                ..
+            } => search_mode_input(&key, reader, history_index, &history),
+            _ => normal_mode_input(&key, &ctx.state),
+        };
+        for action in actions {
+            match action {
+                Action::Command(cmd) => execute_command(&cmd, &mut ctx.state).unwrap(),
+                Action::ModeSwitch(mode) => ctx.state.mode = mode,
+                _ => {}
+            }
+        }
+    };
+    press(&mut ctx, KeyCode::Char('/'));
+    press(&mut ctx, KeyCode::Char('g'));
+    assert_eq!(selected(&ctx), "gamma.rs");
+    press(&mut ctx, KeyCode::Esc);
+    assert!(matches!(ctx.state.mode, Mode::NormalMode));
+    assert_eq!(selected(&ctx), "delta.md");
+    assert_eq!(ctx.state.search.as_ref().unwrap().to_string(), "delta");
+
+    // Each typed step starts from the cursor before the search, so
+    // backspacing goes back to the earlier match
+    press(&mut ctx, KeyCode::Char('/'));
+    press(&mut ctx, KeyCode::Char('a'));
+    assert_eq!(selected(&ctx), "delta.md");
+    press(&mut ctx, KeyCode::Char('l'));
+    assert_eq!(selected(&ctx), "alpha.rs");
+    press(&mut ctx, KeyCode::Backspace);
+    assert_eq!(selected(&ctx), "delta.md");
+    press(&mut ctx, KeyCode::Esc);
+
+    // :search without a pattern clears the search
+    execute_command(":search", &mut ctx.state).unwrap();
+    assert!(ctx.state.search.is_none());
+    assert!(execute_command(":search_next", &mut ctx.state).is_err());
+}
+
+#[test]
+#[serial]
+fn find_test() {
+    let mut ctx = TestContext::new();
+    ctx.state.show_hidden = false;
+    execute_shell_command("!mkdir -p src/deep .hidden", &ctx.state).unwrap();
+    execute_shell_command(
+        "!touch src/deep/needle.rs src/nee_dle.txt .hidden/needle.rs other.txt",
+        &ctx.state,
+    )
+    .unwrap();
+
+    execute_command(":find", &mut ctx.state).unwrap();
+    let press = |ctx: &mut TestContext, code: KeyCode| {
+        let Mode::FindMode(finder) = &mut ctx.state.mode else {
+            panic!("Not finding");
+        };
+        while finder.is_walking() {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let key = KeyEvent::new(code, KeyModifiers::NONE);
+        for action in find_mode_input(&key, finder) {
+            match action {
+                Action::Command(cmd) => execute_command(&cmd, &mut ctx.state).unwrap(),
+                Action::ModeSwitch(mode) => ctx.state.mode = mode,
+                _ => {}
+            }
+        }
+    };
+    for c in "needle".chars() {
+        press(&mut ctx, KeyCode::Char(c));
+    }
+
+    // Dotfiles are left out and the closest match comes first
+    let Mode::FindMode(finder) = &ctx.state.mode else {
+        panic!("Not finding");
+    };
+    let results = finder
+        .results()
+        .iter()
+        .map(|r| r.path.to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(results, ["src/deep/needle.rs", "src/nee_dle.txt"]);
+
+    // Tab selects without leaving the finder and Enter jumps
+    press(&mut ctx, KeyCode::Tab);
+    let needle = ctx.directory.join("src/deep/needle.rs");
+    assert!(ctx.state.multi_select.contains(&needle));
+    press(&mut ctx, KeyCode::Enter);
+    assert!(matches!(ctx.state.mode, Mode::NormalMode));
+    assert_eq!(ctx.state.pwd, ctx.directory.join("src/deep"));
+    assert_eq!(ctx.state.filename_of_selected().unwrap(), "needle.rs");
+
+    // Ranking while walking ends up with the same results as ranking once
+    for i in 0..300 {
+        std::fs::write(ctx.directory.join(format!("src/file{}", i)), "").unwrap();
+    }
+    let ranked = |refresh_while_walking: bool| {
+        let mut finder = Finder::new(ctx.directory.clone(), false, false);
+        finder.reader = PromptReader::new_with_placeholder("fl1", None);
+        while finder.is_walking() {
+            if refresh_while_walking {
+                finder.refresh();
+            }
+        }
+        finder.refresh();
+        finder.results().to_vec()
+    };
+    assert_eq!(ranked(true), ranked(false));
+}
+
+#[test]
+#[serial]
+fn grep_test() {
+    let mut ctx = TestContext::new();
+    ctx.state.show_hidden = false;
+    execute_shell_command("!mkdir src .hidden", &ctx.state).unwrap();
+    let write = |name: &str, contents: &[u8]| std::fs::write(ctx.directory.join(name), contents);
+    write("src/lib.rs", b"a\n// TODO: fix\nb\n// todo later\n").unwrap();
+    write("notes.txt", b"TODO\n").unwrap();
+    write(".hidden/x", b"TODO\n").unwrap();
+    write("blob", b"TODO\0").unwrap();
+
+    // Binaries and dotfiles are skipped, lowercase patterns ignore case
+    assert!(execute_command(":grep '('", &mut ctx.state).is_err());
+    execute_command(":grep todo", &mut ctx.state).unwrap();
+    let Mode::GrepMode(results) = &mut ctx.state.mode else {
+        panic!("No grep results");
+    };
+    while results.is_searching() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    results.refresh();
+    let found = results
+        .matches
+        .iter()
+        .map(|m| format!("{}:{}", m.path.display(), m.line))
+        .collect::<Vec<_>>();
+    assert_eq!(found, ["notes.txt:1", "src/lib.rs:2", "src/lib.rs:4"]);
+
+    // The preview follows the cursor and starts a little above the line
+    let key = KeyEvent::new(KeyCode::Char('j'), KeyModifiers::NONE);
+    grep_mode_input(&key, results);
+    grep_mode_input(&key, results);
+    ctx.state.sync_preview_file();
+    let preview = ctx.state.file_contents.clone().unwrap();
+    assert!(preview.starts_with("// TODO: fix"));
+
+    // Enter jumps to the file and keeps the preview at the line
+    let Mode::GrepMode(results) = &mut ctx.state.mode else {
+        panic!("No grep results");
+    };
+    let key = KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE);
+    for action in grep_mode_input(&key, results) {
+        match action {
+            Action::Command(cmd) => execute_command(&cmd, &mut ctx.state).unwrap(),
+            Action::ModeSwitch(mode) => ctx.state.mode = mode,
+            _ => {}
+        }
+    }
+    assert_eq!(ctx.state.pwd, ctx.directory.join("src"));
+    assert_eq!(ctx.state.filename_of_selected().unwrap(), "lib.rs");
+    ctx.state.sync_preview_file();
+    assert_eq!(ctx.state.file_contents, Some(preview.clone()));
+
+    // The preview is only read again when the highlighted line changes
+    write("src/lib.rs", b"changed\n").unwrap();
+    ctx.state.sync_preview_file();
+    assert_eq!(ctx.state.file_contents, Some(preview));
+
+    execute_command(":cursor_ascend", &mut ctx.state).unwrap();
+    execute_command(":grep TODO", &mut ctx.state).unwrap();
+    let Mode::GrepMode(results) = &mut ctx.state.mode else {
+        panic!("No grep results");
+    };
+    while results.is_searching() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    results.refresh();
+    assert_eq!(results.matches.len(), 1);
+
+    // Ignore files and .git are left out whatever the listings show
+    ctx.state.show_hidden = true;
+    ctx.state.respect_ignore = false;
+    execute_shell_command("!mkdir .git", &ctx.state).unwrap();
+    write(".git/x", b"TODO\n").unwrap();
+    write(".gitignore", b"notes.txt\n").unwrap();
+    execute_command(":grep TODO", &mut ctx.state).unwrap();
+    let Mode::GrepMode(results) = &mut ctx.state.mode else {
+        panic!("No grep results");
+    };
+    while results.is_searching() {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    results.refresh();
+    let found = results
+        .matches
+        .iter()
+        .map(|m| m.path.display().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(found, [".hidden/x"]);
+}
+
+#[test]
+#[serial]
+fn move_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!touch a.txt b.txt c.txt", &ctx.state).unwrap();
+
+    // Missing directories are only created on request
+    assert!(execute_command(":move a.txt b.txt sub/dir", &mut ctx.state).is_err());
+    execute_command(":move -p a.txt b.txt sub/dir", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(ctx.state.pwd.join("sub/dir/a.txt").exists());
+    assert!(ctx.state.pwd.join("sub/dir/b.txt").exists());
+    assert!(!ctx.state.pwd.join("a.txt").exists());
+
+    // Existing entries are only replaced when forced
+    assert!(execute_command(":move c.txt sub/dir/a.txt", &mut ctx.state).is_err());
+    execute_command(":move -f c.txt sub/dir/a.txt", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(!ctx.state.pwd.join("c.txt").exists());
+
+    // Undo restores both the moved and the replaced entry
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert!(ctx.state.pwd.join("c.txt").exists());
+    assert!(ctx.state.pwd.join("sub/dir/a.txt").exists());
+
+    // The rename prompt works for names that look like flags
+    execute_shell_command("!touch -- -f", &ctx.state).unwrap();
+    ctx.state.select_path(&ctx.directory.join("-f")).unwrap();
+    let key = KeyEvent::new(KeyCode::Char('A'), KeyModifiers::NONE);
+    let [Action::ModeSwitch(Mode::CommandMode(reader))] = &normal_mode_input(&key, &ctx.state)[..]
+    else {
+        panic!("Expected the rename prompt");
+    };
+    execute_command(&format!("{}x", reader.result()), &mut ctx.state).unwrap();
+    assert!(ctx.state.pwd.join("-fx").exists());
+
+    // Rename works across directories too
+    assert!(execute_command(":rename c.txt sub/dir/b.txt", &mut ctx.state).is_err());
+    execute_command(":rename c.txt sub/d.txt", &mut ctx.state).unwrap();
+    assert!(ctx.state.pwd.join("sub/d.txt").exists());
+
+    // Refused moves do not leave new directories behind
+    assert!(execute_command(":move -p sub/d.txt missing.txt new", &mut ctx.state).is_err());
+    assert!(!ctx.state.pwd.join("new").exists());
+}
+
 #[test]
 #[serial]
 fn yank_paste_test() {
     let mut ctx = TestContext::new();
     // Create two directories: "from" and "to"
-    execute_shell_command("!mkdir from", &ctx.state.pwd).unwrap();
-    execute_shell_command("!mkdir to", &ctx.state.pwd).unwrap();
+    execute_shell_command("!mkdir from", &ctx.state).unwrap();
+    execute_shell_command("!mkdir to", &ctx.state).unwrap();
 
     // Descend into "from"
     execute_command(":cursor_descend", &mut ctx.state).unwrap();
 
     // Create test file and yank it
-    execute_shell_command("!touch test.txt", &ctx.state.pwd).unwrap();
+    execute_shell_command("!touch test.txt", &ctx.state).unwrap();
     execute_command(":yank test.txt", &mut ctx.state).unwrap();
 
     // Go to "to"-directory
@@ -115,6 +835,7 @@ fn yank_paste_test() {
 
     // Paste
     execute_command(":paste", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
 
     let files = ctx.state.files_in_pwd().unwrap();
     let file_names: Vec<&str> = files
@@ -143,9 +864,9 @@ fn yank_paste_test() {
 fn multi_select() {
     let mut ctx = TestContext::new();
     // Create three test files
-    execute_shell_command("!touch a", &ctx.state.pwd).unwrap();
-    execute_shell_command("!touch b", &ctx.state.pwd).unwrap();
-    execute_shell_command("!touch c", &ctx.state.pwd).unwrap();
+    execute_shell_command("!touch a", &ctx.state).unwrap();
+    execute_shell_command("!touch b", &ctx.state).unwrap();
+    execute_shell_command("!touch c", &ctx.state).unwrap();
 
     // Select two of them
     execute_command(":toggle_select a", &mut ctx.state).unwrap();
@@ -161,3 +882,493 @@ fn multi_select() {
     assert!(selected.contains("a"));
     assert!(selected.contains("b"));
 }
+
+#[test]
+#[serial]
+fn yank_paste_directory_test() {
+    let mut ctx = TestContext::new();
+    // Create a directory tree with a nested file and a symlink
+    execute_shell_command("!mkdir -p from/sub to", &ctx.state).unwrap();
+    execute_shell_command("!touch from/sub/test.txt", &ctx.state).unwrap();
+    execute_shell_command("!chmod 600 from/sub/test.txt", &ctx.state).unwrap();
+    execute_shell_command("!ln -s sub/test.txt from/link", &ctx.state).unwrap();
+    execute_shell_command("!touch -h -d @1000000000 from/link", &ctx.state).unwrap();
+
+    execute_command(":yank from", &mut ctx.state).unwrap();
+
+    // Go to "to"-directory and paste
+    ctx.state.pwd = ctx.directory.join("to");
+    execute_command(":paste", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+
+    let copied = ctx.directory.join("to/from");
+    let copied_file = copied.join("sub/test.txt");
+    assert!(copied_file.is_file());
+    assert_eq!(
+        metadata(&copied_file).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+    assert_eq!(
+        read_link(copied.join("link")).unwrap(),
+        PathBuf::from("sub/test.txt")
+    );
+    assert_eq!(
+        symlink_metadata(copied.join("link")).unwrap().mtime(),
+        1_000_000_000
+    );
+
+    // A directory can not be pasted below itself
+    execute_shell_command("!mkdir -p dir/sub", &ctx.state).unwrap();
+    ctx.state.pwd = ctx.directory.clone();
+    execute_command(":yank dir", &mut ctx.state).unwrap();
+    ctx.state.pwd = ctx.directory.join("dir/sub");
+    assert!(execute_command(":paste", &mut ctx.state).is_err());
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(!ctx.directory.join("dir/sub/dir").exists());
+}
+
+#[test]
+#[serial]
+fn move_by_copy_failure_test() {
+    let ctx = TestContext::new();
+    execute_shell_command("!mkdir -p from/sub", &ctx.state).unwrap();
+    std::fs::write(ctx.directory.join("from/sub/a.txt"), "a").unwrap();
+    // Sockets can't be opened, not even by root
+    let _socket = UnixListener::bind(ctx.directory.join("from/socket")).unwrap();
+
+    // A failed copy leaves the source alone and removes the partial copy
+    let (from, to) = (ctx.directory.join("from"), ctx.directory.join("to"));
+    let mut failed = vec![];
+    move_by_copy(&from, &to, &Progress::new(), &mut failed);
+    assert_eq!(failed.len(), 1);
+    assert_eq!(read_to_string(from.join("sub/a.txt")).unwrap(), "a");
+    assert!(from.join("socket").symlink_metadata().is_ok());
+    assert!(to.symlink_metadata().is_err());
+
+    // Without the socket the tree is moved
+    std::fs::remove_file(from.join("socket")).unwrap();
+    move_by_copy(&from, &to, &Progress::new(), &mut failed);
+    assert_eq!(failed.len(), 1);
+    assert!(!from.exists());
+    assert_eq!(read_to_string(to.join("sub/a.txt")).unwrap(), "a");
+}
+
+#[test]
+#[serial]
+fn cut_paste_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!mkdir -p from/sub to", &ctx.state).unwrap();
+    execute_shell_command("!touch from/test.txt", &ctx.state).unwrap();
+
+    // Cut a file and a directory from "from"
+    ctx.state.pwd = ctx.directory.join("from");
+    execute_command(":cut test.txt sub", &mut ctx.state).unwrap();
+
+    // Paste into "to"
+    ctx.state.pwd = ctx.directory.join("to");
+    execute_command(":paste", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+
+    assert!(ctx.directory.join("to/test.txt").is_file());
+    assert!(ctx.directory.join("to/sub").is_dir());
+    assert!(!ctx.directory.join("from/test.txt").exists());
+    assert!(!ctx.directory.join("from/sub").exists());
+    assert!(ctx.state.yanked.is_empty());
+}
+
+#[test]
+#[serial]
+fn undo_redo_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!touch test.txt", &ctx.state).unwrap();
+    let file_names = |ctx: &TestContext| {
+        ctx.state
+            .files_in_pwd()
+            .unwrap()
+            .iter()
+            .map(|x| x.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    // Undo a rename
+    execute_command(":rename test.txt test2.txt", &mut ctx.state).unwrap();
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert_eq!(file_names(&ctx), vec!["test.txt"]);
+
+    // Undo a delete and redo it
+    execute_command(":delete test.txt", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(file_names(&ctx).is_empty());
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert_eq!(file_names(&ctx), vec!["test.txt"]);
+    execute_command(":redo", &mut ctx.state).unwrap();
+    assert!(file_names(&ctx).is_empty());
+    execute_command(":undo", &mut ctx.state).unwrap();
+
+    // Undo a paste
+    execute_shell_command("!mkdir to", &ctx.state).unwrap();
+    execute_command(":yank test.txt", &mut ctx.state).unwrap();
+    ctx.state.pwd = ctx.directory.join("to");
+    execute_command(":paste", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert_eq!(file_names(&ctx), vec!["test.txt"]);
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert!(file_names(&ctx).is_empty());
+
+    // Everything has been undone
+    assert!(execute_command(":undo", &mut ctx.state).is_err());
+
+    // An entry that can't be undone is dropped and older ones stay reachable
+    ctx.state.pwd = ctx.directory.clone();
+    execute_command(":rename test.txt a.txt", &mut ctx.state).unwrap();
+    execute_command(":rename a.txt b.txt", &mut ctx.state).unwrap();
+    execute_shell_command("!touch a.txt", &ctx.state).unwrap();
+    assert!(execute_command(":undo", &mut ctx.state).is_err());
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert_eq!(file_names(&ctx), vec!["to", "b.txt", "test.txt"]);
+
+    // Holding areas of processes that are gone are swept
+    let held = ctx.directory.join("held");
+    let own = held.join(std::process::id().to_string());
+    create_dir(&held).unwrap();
+    create_dir(&own).unwrap();
+    create_dir(held.join("999999999")).unwrap();
+    Journal::sweep_dead(&held);
+    assert!(own.exists());
+    assert!(!held.join("999999999").exists());
+}
+
+#[test]
+#[serial]
+fn trash_restore_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!touch test.txt", &ctx.state).unwrap();
+    let trash_dir = ctx.directory.with_file_name("test_env_trash");
+
+    execute_command(":trash test.txt", &mut ctx.state).unwrap();
+    assert!(ctx.state.files_in_pwd().unwrap().is_empty());
+    assert!(trash_dir.join("files/test.txt").is_file());
+    let info = read_to_string(trash_dir.join("info/test.txt.trashinfo")).unwrap();
+    assert!(info.starts_with("[Trash Info]\n"));
+    assert!(info.contains(&format!("Path={}\n", ctx.directory.join("test.txt").display())));
+
+    execute_command(":trash_restore test.txt", &mut ctx.state).unwrap();
+    assert!(ctx.directory.join("test.txt").is_file());
+    assert!(!trash_dir.join("info/test.txt.trashinfo").exists());
+
+    // Trashing the same name twice gives unique names in the trash
+    execute_command(":trash test.txt", &mut ctx.state).unwrap();
+    execute_shell_command("!touch test.txt", &ctx.state).unwrap();
+    execute_command(":trash test.txt", &mut ctx.state).unwrap();
+    assert!(trash_dir.join("files/test.txt.2").is_file());
+
+    // A symlink is trashed itself and its target is left alone
+    execute_shell_command("!touch target.txt", &ctx.state).unwrap();
+    execute_shell_command("!ln -s target.txt link", &ctx.state).unwrap();
+    execute_command(":trash link", &mut ctx.state).unwrap();
+    assert!(ctx.directory.join("target.txt").is_file());
+    assert!(ctx.directory.join("link").symlink_metadata().is_err());
+    assert!(trash_dir.join("files/link").is_symlink());
+
+    // Undoing restores the entry, redoing trashes it under the same name
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert!(ctx.directory.join("link").is_symlink());
+    assert!(!trash_dir.join("info/link.trashinfo").exists());
+    execute_command(":redo", &mut ctx.state).unwrap();
+    assert!(trash_dir.join("files/link").is_symlink());
+    assert!(trash_dir.join("info/link.trashinfo").is_file());
+
+    // Emptying the trash has to be confirmed
+    execute_command(":trash_empty", &mut ctx.state).unwrap();
+    let Mode::ConfirmMode { actions, .. } = &ctx.state.mode else {
+        panic!("Expected a confirmation");
+    };
+    assert!(trash_dir.join("files/link").is_symlink());
+    let [Action::Command(confirm)] = actions.as_slice() else {
+        panic!("Expected a single command");
+    };
+    execute_command(&confirm.clone(), &mut ctx.state).unwrap();
+    assert!(read_dir(trash_dir.join("files")).unwrap().next().is_none());
+    assert!(matches!(
+        execute_command(":undo", &mut ctx.state),
+        Err(FilmanError::NothingToUndo)
+    ));
+}
+
+#[test]
+#[serial]
+fn recursive_delete_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!mkdir -p dir/sub", &ctx.state).unwrap();
+    execute_shell_command("!touch dir/a dir/sub/b", &ctx.state).unwrap();
+
+    // Deleting a non-empty directory asks for confirmation first
+    execute_command(":delete dir", &mut ctx.state).unwrap();
+    assert!(ctx.directory.join("dir").is_dir());
+    match &ctx.state.mode {
+        Mode::ConfirmMode { message, .. } => {
+            assert_eq!(message, "delete 2 dirs, 2 files, 0 B? y/N")
+        }
+        mode => panic!("Expected confirm mode, got {:?}", mode),
+    }
+
+    // Huge trees are only counted up to a limit
+    let (summary, complete) = tree_summary_up_to(&ctx.directory.join("dir"), 3).unwrap();
+    assert_eq!((summary.dirs + summary.files, complete), (3, false));
+
+    execute_command(":delete -r dir", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(ctx.state.files_in_pwd().unwrap().is_empty());
+
+    // A file named -r is deleted like any other
+    execute_shell_command("!mkdir dir && touch dir/a -- -r", &ctx.state).unwrap();
+    ctx.state.select_path(&ctx.directory.join("-r")).unwrap();
+    let key = KeyEvent::new(KeyCode::Char('D'), KeyModifiers::NONE);
+    let [Action::ModeSwitch(Mode::CommandMode(reader))] = &normal_mode_input(&key, &ctx.state)[..]
+    else {
+        panic!("Expected the delete prompt");
+    };
+    execute_command(reader.result(), &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(!ctx.directory.join("-r").exists());
+    execute_shell_command("!touch -- -r", &ctx.state).unwrap();
+    execute_command(":delete -- -r dir", &mut ctx.state).unwrap();
+    assert!(matches!(ctx.state.mode, Mode::ConfirmMode { .. }));
+    assert!(ctx.directory.join("dir/a").exists());
+}
+
+#[test]
+#[serial]
+fn quoted_arguments_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!touch 'a b' \"it's\"", &ctx.state).unwrap();
+    assert!(ctx.directory.join("a b").is_file());
+    assert!(ctx.directory.join("it's").is_file());
+
+    execute_command(":delete 'a b' it\\'s", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(ctx.state.files_in_pwd().unwrap().is_empty());
+
+    let err = execute_command(":delete 'a b", &mut ctx.state).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Command parse error: Unbalanced ' quote starting at column 9"
+    );
+
+    // Quoted arguments are tokenized back into the same string
+    assert_eq!(tokenize(&quote("it's a b")).unwrap(), vec!["it's a b"]);
+}
+
+#[test]
+#[serial]
+fn shell_command_output_test() {
+    let ctx = TestContext::new();
+    let output = execute_shell_command("!echo hello", &ctx.state).unwrap();
+    assert_eq!(output, "hello\n");
+
+    let err = execute_shell_command("!ls missing", &ctx.state).unwrap_err();
+    assert!(err.to_string().contains("missing"));
+
+    // The error has the stderr text, both streams are kept for the pane
+    let cmd = "!sh -c 'echo out; echo err >&2; exit 3'";
+    let err = execute_shell_command(cmd, &ctx.state).unwrap_err();
+    let FilmanError::ShellCommandError(message) = &err else {
+        panic!("Expected a failed command");
+    };
+    assert!(message.contains('3') && message.ends_with(": err"));
+    let ran = run_shell_command(cmd, &ctx.state).unwrap();
+    assert_eq!(ran.output, "out\nerr\n");
+    assert!(ran.result.is_err());
+}
+
+#[test]
+#[serial]
+fn output_pane_scroll_test() {
+    let mut pane = OutputPane::new("title".into(), "a\nb\nc\n".into());
+    let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+    for _ in 0..5 {
+        output_mode_input(&key(KeyCode::Char('j')), &mut pane);
+    }
+    assert_eq!(pane.scroll, 2);
+    output_mode_input(&key(KeyCode::Up), &mut pane);
+    assert_eq!(pane.scroll, 1);
+    for _ in 0..3 {
+        pane.scroll_up();
+    }
+    assert_eq!(pane.scroll, 0);
+
+    // Scrolling stops at the limit of the scroll offset
+    let mut pane = OutputPane::new("title".into(), "line\n".repeat(70_000));
+    pane.scroll = u16::MAX - 1;
+    pane.scroll_down();
+    pane.scroll_down();
+    assert_eq!(pane.scroll, u16::MAX);
+
+    let actions = output_mode_input(&key(KeyCode::Char('q')), &mut pane);
+    assert!(matches!(actions[..], [Action::ModeSwitch(Mode::NormalMode)]));
+}
+
+#[test]
+#[serial]
+fn shell_placeholders_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!touch 'a b' c", &ctx.state).unwrap();
+    execute_command(":toggle_select 'a b'", &mut ctx.state).unwrap();
+    execute_command(":toggle_select c", &mut ctx.state).unwrap();
+
+    // A standalone placeholder expands to one argument per file
+    let output = execute_shell_command("!ls -1 $fs", &ctx.state).unwrap();
+    let expected = format!(
+        "{}\n{}\n",
+        ctx.directory.join("a b").display(),
+        ctx.directory.join("c").display()
+    );
+    assert_eq!(output, expected);
+
+    // Single quotes keep the placeholder literally
+    let output = execute_shell_command("!echo '$d' \"in $d\"", &ctx.state).unwrap();
+    assert_eq!(output, format!("$d in {}\n", ctx.directory.display()));
+
+    let output = execute_shell_command("!printenv FILMAN_SELECTION", &ctx.state).unwrap();
+    assert_eq!(
+        output,
+        format!(
+            "'{}' {}\n",
+            ctx.directory.join("a b").display(),
+            ctx.directory.join("c").display()
+        )
+    );
+
+    let output = execute_shell_command("!printenv FILMAN_SELECTED", &ctx.state).unwrap();
+    assert_eq!(output, format!("{}\n", ctx.directory.join("a b").display()));
+}
+
+#[test]
+#[serial]
+fn open_with_handler_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!touch test.txt", &ctx.state).unwrap();
+    ctx.state.open_handlers = vec![OpenHandler {
+        extensions: vec!["TXT".into()],
+        mime: vec![],
+        command: "cp $f opened.txt".into(),
+        mode: LaunchMode::Detached,
+    }];
+
+    execute_command(":open test.txt", &mut ctx.state).unwrap();
+
+    // The handler runs in the background
+    let opened = ctx.directory.join("opened.txt");
+    for _ in 0..100 {
+        if opened.exists() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(opened.is_file());
+
+    // Only the exact placeholder keeps the file from being appended
+    assert!(uses_variable("cp \"$f\" opened.txt", "f").unwrap());
+    assert!(!uses_variable("viewer $fs", "f").unwrap());
+    assert!(!uses_variable("echo '$f'", "f").unwrap());
+}
+
+#[test]
+#[serial]
+fn paste_job_progress_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!mkdir to", &ctx.state).unwrap();
+    std::fs::write(ctx.directory.join("test.txt"), "0123456789").unwrap();
+    execute_command(":yank test.txt", &mut ctx.state).unwrap();
+
+    ctx.state.pwd = ctx.directory.join("to");
+    execute_command(":paste", &mut ctx.state).unwrap();
+    let job = ctx.state.jobs.iter().next().unwrap().clone();
+    ctx.state.wait_for_jobs().unwrap();
+
+    assert!(ctx.state.jobs.is_empty());
+    assert_eq!(job.progress.files_done.load(Ordering::Relaxed), 1);
+    assert_eq!(job.progress.bytes_done.load(Ordering::Relaxed), 10);
+    assert_eq!(job.progress.bytes_total.load(Ordering::Relaxed), 10);
+
+    assert!(execute_command(":cancel 42", &mut ctx.state).is_err());
+
+    // A running job keeps its place in the journal
+    execute_shell_command("!touch a.txt c.txt", &ctx.state).unwrap();
+    let (from, to) = (ctx.state.pwd.join("a.txt"), ctx.state.pwd.join("b.txt"));
+    let (start, started) = std::sync::mpsc::channel::<()>();
+    ctx.state.spawn_job("slow".into(), move |_| {
+        started.recv().unwrap();
+        std::fs::rename(&from, &to).unwrap();
+        JobOutcome {
+            operation: Some(Operation::Rename { from, to }),
+            result: Ok(()),
+        }
+    });
+    execute_command(":rename c.txt d.txt", &mut ctx.state).unwrap();
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert!(ctx.state.pwd.join("c.txt").exists());
+    assert!(execute_command(":undo", &mut ctx.state).is_err());
+
+    start.send(()).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert!(ctx.state.pwd.join("a.txt").exists());
+}
+
+#[test]
+#[serial]
+fn paste_conflict_policy_test() {
+    let mut ctx = TestContext::new();
+    execute_shell_command("!mkdir to", &ctx.state).unwrap();
+    execute_shell_command("!touch a.txt b.txt to/a.txt", &ctx.state).unwrap();
+    std::fs::write(ctx.directory.join("a.txt"), "new").unwrap();
+    execute_command(":yank a.txt b.txt", &mut ctx.state).unwrap();
+    ctx.state.pwd = ctx.directory.join("to");
+
+    // The default policy refuses to paste anything if one entry collides
+    assert!(execute_command(":paste", &mut ctx.state).is_err());
+    assert!(!ctx.directory.join("to/b.txt").exists());
+
+    execute_command(":paste --policy=rename", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(ctx.directory.join("to/a (1).txt").is_file());
+    assert!(ctx.directory.join("to/b.txt").is_file());
+
+    // Overwritten entries come back on undo
+    ctx.state.pwd = ctx.directory.clone();
+    execute_command(":yank a.txt", &mut ctx.state).unwrap();
+    ctx.state.pwd = ctx.directory.join("to");
+    execute_command(":paste --policy=overwrite", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert_eq!(read_to_string(ctx.directory.join("to/a.txt")).unwrap(), "new");
+    execute_command(":undo", &mut ctx.state).unwrap();
+    assert_eq!(read_to_string(ctx.directory.join("to/a.txt")).unwrap(), "");
+
+    // Asking resolves one conflict at a time
+    execute_command(":paste --policy=ask", &mut ctx.state).unwrap();
+    assert!(matches!(ctx.state.mode, Mode::ConflictMode(_)));
+    execute_command(":resolve_conflict skip", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(matches!(ctx.state.mode, Mode::NormalMode));
+    assert_eq!(read_to_string(ctx.directory.join("to/a.txt")).unwrap(), "");
+
+    // No policy pastes a directory below itself
+    ctx.state.pwd = ctx.directory.clone();
+    execute_shell_command("!mkdir -p dir/sub/dir", &ctx.state).unwrap();
+    execute_command(":yank dir", &mut ctx.state).unwrap();
+    ctx.state.pwd = ctx.directory.join("dir/sub");
+    for policy in ["skip", "overwrite", "rename", "overwrite-if-newer", "ask"] {
+        let paste = format!(":paste --policy={}", policy);
+        assert!(execute_command(&paste, &mut ctx.state).is_err());
+    }
+    assert!(!ctx.directory.join("dir/sub/dir (1)").exists());
+
+    // Nor does it overwrite a directory the pasted entry is in
+    ctx.state.pwd = ctx.directory.clone();
+    execute_shell_command("!touch dir/sub/dir/f", &ctx.state).unwrap();
+    execute_command(":yank dir/sub/dir", &mut ctx.state).unwrap();
+    execute_command(":paste --policy=overwrite", &mut ctx.state).unwrap();
+    ctx.state.wait_for_jobs().unwrap();
+    assert!(ctx.directory.join("dir/sub/dir/f").exists());
+}
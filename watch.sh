@@ -1,4 +1,4 @@
 #!/bin/bash
 set -ex
 
-cargo watch -x test -i test_env --clear
+cargo watch -x test -i test_env -i test_env_held -i test_env_trash --clear